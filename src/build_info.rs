@@ -0,0 +1,99 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Serialize;
+
+use crate::errors::*;
+use crate::extensions::CommandExt;
+
+/// The version of `cross`, with the commit it was built from, as in `--version`
+pub const CROSS_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    include_str!(concat!(env!("OUT_DIR"), "/commit-info.txt"))
+);
+
+/// The file written next to the artifacts of a target
+const FILE_NAME: &str = "cross-build-info.json";
+
+/// The provenance of a successful build, for the tooling that packages its
+/// artifacts. The keys are stable.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BuildInfo<'a> {
+    pub target: &'a str,
+    /// The toolchain on the host, if managed by rustup
+    pub toolchain: Option<&'a str>,
+    pub image: &'a str,
+    pub cross_version: &'a str,
+    /// The commit checked out in the project, if it's a git repository
+    pub source_commit: Option<String>,
+}
+
+impl BuildInfo<'_> {
+    /// Writes the build info to `<target_dir>/<target>/cross-build-info.json`
+    pub fn write(&self, target_dir: &Path) -> Result<PathBuf> {
+        let dir = target_dir.join(self.target);
+        fs::create_dir_all(&dir)
+            .wrap_err_with(|| format!("couldn't create `{}`", dir.display()))?;
+        let path = dir.join(FILE_NAME);
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(&path, json + "\n")
+            .wrap_err_with(|| format!("couldn't write `{}`", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// The commit checked out in `root`, if it's in a git repository
+pub fn source_commit(root: &Path, verbose: bool) -> Option<String> {
+    Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["rev-parse", "HEAD"])
+        .run_and_get_stdout(verbose)
+        .ok()
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_next_to_artifacts() -> Result<()> {
+        let target_dir =
+            std::env::temp_dir().join(format!("cross-build-info-{}", std::process::id()));
+        let info = BuildInfo {
+            target: "aarch64-unknown-linux-gnu",
+            toolchain: Some("stable-x86_64-unknown-linux-gnu"),
+            image: "ghcr.io/cross-rs/aarch64-unknown-linux-gnu:main",
+            cross_version: "0.2.1",
+            source_commit: Some("0123456789abcdef".to_string()),
+        };
+        let path = info.write(&target_dir)?;
+        assert_eq!(
+            path,
+            target_dir
+                .join("aarch64-unknown-linux-gnu")
+                .join("cross-build-info.json")
+        );
+
+        let written: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        let mut keys: Vec<_> = written.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "cross_version",
+                "image",
+                "source_commit",
+                "target",
+                "toolchain"
+            ]
+        );
+        assert_eq!(written["toolchain"], "stable-x86_64-unknown-linux-gnu");
+
+        fs::remove_dir_all(target_dir).ok();
+        Ok(())
+    }
+}
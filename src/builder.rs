@@ -0,0 +1,119 @@
+//! Running `cross` from another program, instead of its command line
+
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+use crate::errors::*;
+use crate::{cli, rustc, Config};
+
+/// Runs a build like the `cross` binary does with its command line. The
+/// environment still applies, like the `CROSS_*` variables do to the binary.
+///
+/// ```no_run
+/// let outcome = cross::CrossBuilder::new()
+///     .args(["build", "--release"])
+///     .target("aarch64-unknown-linux-gnu")
+///     .run()?;
+/// assert!(outcome.status.map_or(false, |status| status.success()));
+/// # Ok::<(), eyre::Report>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct CrossBuilder {
+    args: Vec<String>,
+    targets: Vec<String>,
+    config_path: Option<PathBuf>,
+}
+
+/// What a `CrossBuilder` ran
+#[derive(Debug)]
+pub struct CrossOutcome {
+    /// The exit status of cargo, the first failed one when building for
+    /// several targets. `None` if it wasn't run.
+    pub status: Option<ExitStatus>,
+    /// The configuration of the project, `None` outside a Cargo project
+    pub config: Option<Config>,
+}
+
+impl CrossBuilder {
+    pub fn new() -> Self {
+        CrossBuilder::default()
+    }
+
+    /// Adds arguments, as they're given to `cross`: the subcommand first
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Builds for `target`, which can be called for several targets
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.targets.push(target.into());
+        self
+    }
+
+    /// Reads the configuration from `path`, instead of `CROSS_CONFIG` or the
+    /// project's `Cross.toml`
+    pub fn config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// The arguments, with the targets before any `--`
+    fn command_line(&self) -> Vec<String> {
+        let mut all = self.args.clone();
+        let index = all.iter().position(|a| a == "--").unwrap_or(all.len());
+        let targets = self
+            .targets
+            .iter()
+            .flat_map(|target| ["--target".to_string(), target.clone()]);
+        all.splice(index..index, targets);
+        all
+    }
+
+    pub fn run(self) -> Result<CrossOutcome> {
+        let target_list = rustc::target_list(false)?;
+        let mut args = cli::parse_args(self.command_line(), &target_list);
+        args.config_path = self.config_path.or(args.config_path);
+
+        let config = crate::project_config(&args)?;
+        let args = crate::expand_all_targets(args, &target_list)?;
+        let mut status: Option<ExitStatus> = None;
+        for args in args.per_target() {
+            let ran = crate::run(args, &target_list)?;
+            if status.map_or(true, |status| status.success()) {
+                status = ran.or(status);
+            }
+        }
+
+        Ok(CrossOutcome { status, config })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_before_separator() {
+        let builder = CrossBuilder::new()
+            .args(["test", "--", "--nocapture"])
+            .target("aarch64-unknown-linux-gnu")
+            .target("armv7-unknown-linux-gnueabihf");
+        assert_eq!(
+            builder.command_line(),
+            [
+                "test",
+                "--target",
+                "aarch64-unknown-linux-gnu",
+                "--target",
+                "armv7-unknown-linux-gnueabihf",
+                "--",
+                "--nocapture",
+            ]
+        );
+    }
+}
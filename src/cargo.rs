@@ -10,6 +10,7 @@ pub enum Subcommand {
     Build,
     Check,
     Doc,
+    Fix,
     Other,
     Run,
     Rustc,
@@ -18,28 +19,46 @@ pub enum Subcommand {
     Deb,
     Clippy,
     Metadata,
+    Clean,
+    Update,
+    GenerateLockfile,
+    CheckConfig,
+    SetupInterpreters,
+    Shell,
 }
 
 impl Subcommand {
     pub fn needs_docker(self) -> bool {
-        !matches!(self, Subcommand::Other)
+        !matches!(
+            self,
+            Subcommand::Other
+                | Subcommand::Clean
+                | Subcommand::CheckConfig
+                | Subcommand::SetupInterpreters
+        )
     }
 
     pub fn needs_interpreter(self) -> bool {
         matches!(self, Subcommand::Run | Subcommand::Test | Subcommand::Bench)
     }
 
+    /// Whether the subcommand takes `--target`. The ones that resolve the
+    /// lockfile run in the container as well, but don't take it.
     pub fn needs_target_in_command(self) -> bool {
-        !matches!(self, Subcommand::Metadata)
+        !matches!(
+            self,
+            Subcommand::Metadata | Subcommand::Update | Subcommand::GenerateLockfile
+        )
     }
 }
 
-impl<'a> From<&'a str> for Subcommand {
+impl From<&str> for Subcommand {
     fn from(s: &str) -> Subcommand {
         match s {
             "b" | "build" => Subcommand::Build,
             "c" | "check" => Subcommand::Check,
             "doc" => Subcommand::Doc,
+            "fix" => Subcommand::Fix,
             "r" | "run" => Subcommand::Run,
             "rustc" => Subcommand::Rustc,
             "t" | "test" => Subcommand::Test,
@@ -47,6 +66,12 @@ impl<'a> From<&'a str> for Subcommand {
             "deb" => Subcommand::Deb,
             "clippy" => Subcommand::Clippy,
             "metadata" => Subcommand::Metadata,
+            "clean" => Subcommand::Clean,
+            "update" => Subcommand::Update,
+            "generate-lockfile" => Subcommand::GenerateLockfile,
+            "check-config" => Subcommand::CheckConfig,
+            "setup-interpreters" => Subcommand::SetupInterpreters,
+            "shell" => Subcommand::Shell,
             _ => Subcommand::Other,
         }
     }
@@ -66,15 +91,19 @@ impl Root {
 /// Cargo project root
 pub fn root() -> Result<Option<Root>> {
     let cd = env::current_dir().wrap_err("couldn't get current directory")?;
+    Ok(find_root(&cd))
+}
 
-    let mut dir = &*cd;
+/// The root of the Cargo project `dir` is in: the closest directory, from
+/// `dir` up, with a manifest
+pub fn find_root(mut dir: &Path) -> Option<Root> {
     loop {
         let toml = dir.join("Cargo.toml");
 
         if fs::metadata(&toml).is_ok() {
-            return Ok(Some(Root {
+            return Some(Root {
                 path: dir.to_owned(),
-            }));
+            });
         }
 
         match dir.parent() {
@@ -83,10 +112,264 @@ pub fn root() -> Result<Option<Root>> {
         }
     }
 
-    Ok(None)
+    None
+}
+
+/// The root of the workspace `dir` belongs to: the closest directory, from
+/// `dir` up, with a manifest that has a `[workspace]` table
+pub fn workspace_root(dir: &Path) -> Option<&Path> {
+    dir.ancestors().find(|dir| {
+        fs::read_to_string(dir.join("Cargo.toml"))
+            .ok()
+            .and_then(|manifest| manifest.parse::<toml::Value>().ok())
+            .map_or(false, |manifest| manifest.get("workspace").is_some())
+    })
+}
+
+/// The targets of `build.target` in the cargo configuration of `dir`: the
+/// closest `.cargo/config.toml` from `dir` up that sets it, then the one of
+/// the cargo home. Paths to target specifications are relative to the
+/// directory holding the `.cargo` directory, and are made relative to `dir`,
+/// the way cargo takes them on the command line.
+pub fn config_targets(dir: &Path, cargo_home: Option<&Path>) -> Result<Vec<String>> {
+    let config_dirs = dir
+        .ancestors()
+        .map(|ancestor| ancestor.join(".cargo"))
+        .chain(cargo_home.map(Path::to_path_buf));
+    for config_dir in config_dirs {
+        // like cargo, the legacy `config` wins when both exist
+        let path = match ["config", "config.toml"]
+            .iter()
+            .map(|name| config_dir.join(name))
+            .find(|path| path.is_file())
+        {
+            Some(path) => path,
+            None => continue,
+        };
+        let content = fs::read_to_string(&path)
+            .wrap_err_with(|| format!("couldn't read `{}`", path.display()))?;
+        let config: toml::Value = content
+            .parse()
+            .wrap_err_with(|| format!("couldn't parse `{}`", path.display()))?;
+        let targets = match config.get("build").and_then(|build| build.get("target")) {
+            Some(toml::Value::String(target)) => vec![target.as_str()],
+            Some(toml::Value::Array(targets)) => targets
+                .iter()
+                .map(|target| target.as_str())
+                .collect::<Option<_>>()
+                .ok_or_else(|| {
+                    eyre::eyre!("`build.target` in `{}` has a non-string", path.display())
+                })?,
+            Some(_) => eyre::bail!(
+                "`build.target` in `{}` must be a string or an array of strings",
+                path.display()
+            ),
+            None => continue,
+        };
+
+        let base = config_dir.parent().unwrap_or(&config_dir);
+        return Ok(targets
+            .into_iter()
+            .map(|target| {
+                if target.ends_with(".json") && Path::new(target).is_relative() {
+                    relative_path(&base.join(target), dir)
+                        .to_string_lossy()
+                        .into_owned()
+                } else {
+                    target.to_string()
+                }
+            })
+            .collect());
+    }
+
+    Ok(Vec::new())
+}
+
+/// `path` relative to `dir`, both being absolute
+fn relative_path(path: &Path, dir: &Path) -> PathBuf {
+    let common = path
+        .components()
+        .zip(dir.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    dir.components()
+        .skip(common)
+        .map(|_| Path::new(".."))
+        .chain(
+            path.components()
+                .skip(common)
+                .map(|c| Path::new(c.as_os_str())),
+        )
+        .collect()
+}
+
+/// Checks that `Cargo.lock` exists in the mounted project root and is up to
+/// date, so `--locked` failures are attributed to the lockfile.
+pub fn check_lockfile(root: &Root, verbose: bool) -> Result<()> {
+    let lockfile = root.path().join("Cargo.lock");
+    if !lockfile.exists() {
+        return Err(eyre::eyre!(
+            "`--locked` was passed, but there is no lockfile at `{}`",
+            lockfile.display()
+        ))
+        .suggestion("the lockfile must be in the project root, which is the directory mounted into the container");
+    }
+
+    let mut metadata = Command::new("cargo");
+    metadata
+        .args([
+            "metadata",
+            "--format-version=1",
+            "--locked",
+            "--manifest-path",
+        ])
+        .arg(root.path().join("Cargo.toml"));
+    metadata.print_verbose(verbose);
+    let out = metadata
+        .output()
+        .wrap_err_with(|| format!("couldn't execute `{:?}`", metadata))?;
+
+    // other failures are left for cargo inside the container to report
+    if out.status.success() {
+        Ok(())
+    } else {
+        lockfile_error(&lockfile, &String::from_utf8_lossy(&out.stderr))
+    }
+}
+
+fn lockfile_error(lockfile: &Path, stderr: &str) -> Result<()> {
+    match stderr
+        .lines()
+        .find(|l| l.starts_with("error:") && l.contains("--locked"))
+    {
+        Some(line) => Err(eyre::eyre!("`{}` is out of date", lockfile.display()))
+            .note(line.trim_start_matches("error: ").to_string())
+            .suggestion("run `cargo update` to update the lockfile, or drop `--locked`"),
+        None => Ok(()),
+    }
+}
+
+/// The test executables among the JSON messages of a build
+pub fn test_executables(messages: &str) -> Vec<PathBuf> {
+    messages
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|message| {
+            message["reason"] == "compiler-artifact" && message["profile"]["test"] == true
+        })
+        .filter_map(|message| message["executable"].as_str().map(PathBuf::from))
+        .collect()
+}
+
+/// Pass-through mode, with the configured `cargo`
+pub fn run(cargo: &str, args: &[String], verbose: bool) -> Result<ExitStatus> {
+    Command::new(cargo).args(args).run_and_get_status(verbose)
 }
 
-/// Pass-through mode
-pub fn run(args: &[String], verbose: bool) -> Result<ExitStatus> {
-    Command::new("cargo").args(args).run_and_get_status(verbose)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockfile_subcommands() {
+        assert_eq!(Subcommand::from("update"), Subcommand::Update);
+        assert_eq!(
+            Subcommand::from("generate-lockfile"),
+            Subcommand::GenerateLockfile
+        );
+        for sc in [Subcommand::Update, Subcommand::GenerateLockfile] {
+            assert!(sc.needs_docker());
+            assert!(!sc.needs_target_in_command());
+            assert!(!sc.needs_interpreter());
+        }
+        assert_eq!(Subcommand::from("upgrade"), Subcommand::Other);
+    }
+
+    #[test]
+    fn setup_interpreters_subcommand() {
+        let sc = Subcommand::from("setup-interpreters");
+        assert_eq!(sc, Subcommand::SetupInterpreters);
+        assert!(!sc.needs_docker());
+        assert!(!sc.needs_interpreter());
+    }
+
+    #[test]
+    fn find_test_executables() {
+        let messages = r#"{"reason":"compiler-artifact","profile":{"test":false},"executable":null}
+{"reason":"compiler-artifact","profile":{"test":true},"executable":"/target/aarch64-unknown-linux-gnu/debug/deps/hello-0123456789abcdef"}
+{"reason":"compiler-artifact","profile":{"test":false},"executable":"/target/aarch64-unknown-linux-gnu/debug/hello"}
+{"reason":"build-finished","success":true}
+"#;
+        assert_eq!(
+            test_executables(messages),
+            [PathBuf::from(
+                "/target/aarch64-unknown-linux-gnu/debug/deps/hello-0123456789abcdef"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_out_of_date_lockfile() {
+        let lockfile = Path::new("/project/Cargo.lock");
+        let stderr = "    Updating crates.io index\n\
+            error: the lock file /project/Cargo.lock needs to be updated but --locked was passed to prevent this\n\
+            If you want to try to generate the lock file without accessing the network, remove the --locked flag and use --offline instead.\n";
+        let err = lockfile_error(lockfile, stderr).unwrap_err();
+        assert_eq!(err.to_string(), "`/project/Cargo.lock` is out of date");
+    }
+
+    #[test]
+    fn test_config_targets() -> Result<()> {
+        let dir = env::temp_dir().join(format!("cross-cargo-config-{}", std::process::id()));
+        let member = dir.join("crates").join("member");
+        fs::create_dir_all(&member)?;
+        fs::create_dir_all(dir.join(".cargo"))?;
+        assert!(config_targets(&member, None)?.is_empty());
+
+        fs::write(
+            dir.join(".cargo/config.toml"),
+            "[build]\ntarget = \"aarch64-unknown-linux-gnu\"\n",
+        )?;
+        assert_eq!(
+            config_targets(&member, None)?,
+            ["aarch64-unknown-linux-gnu"]
+        );
+
+        // the closest configuration wins, and spec paths follow the directory
+        fs::create_dir_all(member.join(".cargo"))?;
+        fs::write(
+            member.join(".cargo/config.toml"),
+            "[build]\ntarget = [\"armv7-unknown-linux-gnueabihf\", \"specs/custom.json\"]\n",
+        )?;
+        assert_eq!(
+            config_targets(&member, None)?,
+            ["armv7-unknown-linux-gnueabihf", "specs/custom.json"]
+        );
+        let src = member.join("src");
+        fs::create_dir_all(&src)?;
+        assert_eq!(
+            config_targets(&src, None)?,
+            [
+                "armv7-unknown-linux-gnueabihf".to_string(),
+                Path::new("..")
+                    .join("specs")
+                    .join("custom.json")
+                    .to_string_lossy()
+                    .into_owned()
+            ]
+        );
+
+        fs::write(member.join(".cargo/config.toml"), "[build]\ntarget = 1\n")?;
+        assert!(config_targets(&member, None).is_err());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_unrelated_metadata_failure() {
+        let lockfile = Path::new("/project/Cargo.lock");
+        let stderr = "error: failed to get `foo` as a dependency of package `bar`\n";
+        assert!(lockfile_error(lockfile, stderr).is_ok());
+    }
 }
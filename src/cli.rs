@@ -5,41 +5,227 @@ use crate::cargo::Subcommand;
 use crate::rustc::TargetList;
 use crate::Target;
 
-#[derive(Debug)]
+/// The `--target` expanding to the targets of `build.targets`
+const ALL_TARGETS: &str = "all";
+
+#[derive(Debug, Clone)]
 pub struct Args {
     pub all: Vec<String>,
     pub subcommand: Option<Subcommand>,
     pub channel: Option<String>,
     pub target: Option<Target>,
+    /// All the targets given, with repeated `--target` flags or comma lists
+    pub targets: Vec<Target>,
+    /// Whether `--target all` was given, for the targets of `build.targets`
+    pub all_targets: bool,
     pub target_dir: Option<PathBuf>,
     pub docker_in_docker: bool,
+    pub json_messages: bool,
+    pub emit_mounts: Option<PathBuf>,
+    pub locked: bool,
+    pub color: Option<String>,
+    pub engine_args: Vec<String>,
+    pub verbose: bool,
+    pub no_run: bool,
+    pub server: bool,
+    /// Whether the container of a failed build is kept, for debugging
+    pub keep_container_on_failure: bool,
+    /// Whether `--timings` was given, which is also passed on to cargo
+    pub timings: bool,
+    pub manifest_path: Option<PathBuf>,
+    /// The `Cross.toml` given with `--cross-config`, or to a `CrossBuilder`
+    pub config_path: Option<PathBuf>,
+}
+
+impl Args {
+    /// Whether the built executables are run, which needs a registered
+    /// interpreter for emulated targets. They aren't with `--no-run`.
+    pub fn needs_interpreter(&self) -> bool {
+        self.subcommand.map_or(false, |sc| sc.needs_interpreter()) && !self.no_run
+    }
+
+    /// Whether the subcommand builds a Cargo project. `--version`, `--help`
+    /// and the subcommands `cross` doesn't know are passed on to `cargo`
+    /// as they are.
+    pub fn needs_project(&self) -> bool {
+        self.subcommand.map_or(false, |sc| sc != Subcommand::Other)
+    }
+
+    /// The profile of the build, by the name of its directory in the target
+    /// directory: `debug` unless `--release` or `--profile` say otherwise
+    pub fn profile(&self) -> String {
+        let mut profile = None;
+        let mut args = self.all.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                break;
+            } else if arg == "--release" || arg == "-r" {
+                profile = Some("release");
+            } else if arg == "--profile" {
+                profile = args.next().map(String::as_str);
+            } else if let Some(name) = arg.strip_prefix("--profile=") {
+                profile = Some(name);
+            }
+        }
+
+        match profile {
+            None | Some("dev" | "test") => "debug",
+            Some("bench") => "release",
+            Some(name) => name,
+        }
+        .to_string()
+    }
+
+    /// The arguments with `--target all` replaced by `targets`
+    pub fn with_targets(self, targets: Vec<Target>) -> Args {
+        let mut all = without_targets(&self.all);
+        let index = all.iter().position(|a| a == "--").unwrap_or(all.len());
+        let targets: Vec<_> = self.targets.iter().cloned().chain(targets).collect();
+        all.splice(
+            index..index,
+            targets
+                .iter()
+                .flat_map(|target| ["--target".to_string(), target.triple().to_string()]),
+        );
+        Args {
+            all,
+            target: targets.last().cloned(),
+            targets,
+            all_targets: false,
+            ..self
+        }
+    }
+
+    /// The arguments of a build for each target, which are run one after the
+    /// other when several are given
+    pub fn per_target(self) -> Vec<Args> {
+        if self.targets.len() <= 1 {
+            return vec![self];
+        }
+
+        let all = without_targets(&self.all);
+        let index = all.iter().position(|a| a == "--").unwrap_or(all.len());
+        self.targets
+            .iter()
+            .map(|target| {
+                let mut all = all.clone();
+                all.splice(
+                    index..index,
+                    ["--target".to_string(), target.triple().to_string()],
+                );
+                Args {
+                    all,
+                    target: Some(target.clone()),
+                    targets: vec![target.clone()],
+                    ..self.clone()
+                }
+            })
+            .collect()
+    }
+}
+
+/// The arguments without the `--target` flags meant for `cargo`
+fn without_targets(all: &[String]) -> Vec<String> {
+    let mut without = vec![];
+    let mut args = all.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            without.push(arg.clone());
+            without.extend(args.cloned());
+            break;
+        } else if arg == "--target" {
+            args.next();
+        } else if !arg.starts_with("--target=") {
+            without.push(arg.clone());
+        }
+    }
+    without
+}
+
+/// The targets of a `--target` value, which may be a comma-separated list.
+/// `all` is left out, and expanded from the configuration later.
+fn parse_targets(value: &str, target_list: &TargetList) -> Vec<Target> {
+    value
+        .split(',')
+        .filter(|t| !t.is_empty() && *t != ALL_TARGETS)
+        .map(|t| Target::from(t, target_list))
+        .collect()
+}
+
+fn has_all_targets(value: &str) -> bool {
+    value.split(',').any(|t| t == ALL_TARGETS)
+}
+
+fn is_json_format(format: &str) -> bool {
+    format.split(',').any(|f| f.starts_with("json"))
+}
+
+/// Resolves the color preference of `cross` and the `cargo` it runs: an
+/// explicit `--color` wins over `CARGO_TERM_COLOR`, which wins over `NO_COLOR`.
+/// `None` means `auto`.
+fn color_choice(
+    explicit: Option<&str>,
+    cargo_term_color: Option<&str>,
+    no_color: Option<&str>,
+) -> Option<String> {
+    let non_empty = |v: &&str| !v.is_empty();
+    explicit
+        .or_else(|| cargo_term_color.filter(non_empty))
+        .map(ToString::to_string)
+        .or_else(|| no_color.filter(non_empty).map(|_| "never".to_string()))
+}
+
+/// Resolves a relative path against the current directory
+fn absolute(path: PathBuf) -> PathBuf {
+    env::current_dir().map_or_else(|_| path.clone(), |cd| cd.join(&path))
+}
+
+/// Adds `--color` to the arguments for `cargo`, before any `--`
+fn forward_color(all: &mut Vec<String>, color: &str) {
+    let index = all.iter().position(|a| a == "--").unwrap_or(all.len());
+    all.splice(index..index, ["--color".to_string(), color.to_string()]);
 }
 
 pub fn parse(target_list: &TargetList) -> Args {
+    parse_args(env::args().skip(1), target_list)
+}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>, target_list: &TargetList) -> Args {
     let mut channel = None;
-    let mut target = None;
+    let mut targets = vec![];
     let mut target_dir = None;
     let mut sc = None;
+    let mut json_messages = false;
+    let mut emit_mounts = None;
+    let mut explicit_color = None;
+    let mut engine_args = vec![];
+    let mut server = false;
+    let mut keep_container_on_failure = false;
+    let mut all_targets = false;
+    let mut manifest_path = None;
+    let mut config_path = None;
     let mut all: Vec<String> = Vec::new();
 
     {
-        let mut args = env::args().skip(1);
+        let mut args = args.into_iter();
         while let Some(arg) = args.next() {
             if arg.is_empty() {
                 continue;
             }
-            if let ("+", ch) = arg.split_at(1) {
-                channel = Some(ch.to_string());
+            // like rustup's proxies, only a `+toolchain` before the subcommand
+            // picks the toolchain, and isn't passed on to cargo
+            if let Some(toolchain) = arg.strip_prefix('+').filter(|_| sc.is_none()) {
+                channel = Some(toolchain.to_string());
             } else if arg == "--target" {
                 all.push(arg);
                 if let Some(t) = args.next() {
-                    target = Some(Target::from(&t, target_list));
+                    targets.extend(parse_targets(&t, target_list));
+                    all_targets |= has_all_targets(&t);
                     all.push(t);
                 }
-            } else if arg.starts_with("--target=") {
-                target = arg
-                    .split_once('=')
-                    .map(|(_, t)| Target::from(t, target_list));
+            } else if let Some(t) = arg.strip_prefix("--target=") {
+                targets.extend(parse_targets(t, target_list));
+                all_targets |= has_all_targets(t);
                 all.push(arg);
             } else if arg == "--target-dir" {
                 all.push(arg);
@@ -52,6 +238,51 @@ pub fn parse(target_list: &TargetList) -> Args {
                     target_dir = Some(PathBuf::from(&td));
                     all.push("--target-dir=/target".into());
                 }
+            } else if arg == "--emit-mounts" {
+                emit_mounts = args.next().map(PathBuf::from);
+            } else if arg.starts_with("--emit-mounts=") {
+                emit_mounts = arg.split_once('=').map(|(_, p)| PathBuf::from(p));
+            } else if arg == "--engine-arg" {
+                engine_args.extend(args.next());
+            } else if let Some(engine_arg) = arg.strip_prefix("--engine-arg=") {
+                engine_args.push(engine_arg.to_string());
+            } else if arg == "--manifest-path" {
+                all.push(arg);
+                if let Some(path) = args.next() {
+                    manifest_path = Some(PathBuf::from(&path));
+                    all.push(path);
+                }
+            } else if arg.starts_with("--manifest-path=") {
+                manifest_path = arg.split_once('=').map(|(_, p)| PathBuf::from(p));
+                all.push(arg);
+            } else if arg == "--cross-config" {
+                config_path = args.next().map(|path| absolute(path.into()));
+            } else if let Some(path) = arg.strip_prefix("--cross-config=") {
+                config_path = Some(absolute(path.into()));
+            } else if arg == "--server" {
+                server = true;
+            } else if arg == "--keep-container-on-failure" {
+                keep_container_on_failure = true;
+            } else if arg == "--color" {
+                all.push(arg);
+                if let Some(color) = args.next() {
+                    explicit_color = Some(color.clone());
+                    all.push(color);
+                }
+            } else if arg.starts_with("--color=") {
+                explicit_color = arg.split_once('=').map(|(_, c)| c.to_string());
+                all.push(arg);
+            } else if arg == "--message-format" {
+                all.push(arg);
+                if let Some(format) = args.next() {
+                    json_messages = is_json_format(&format);
+                    all.push(format);
+                }
+            } else if arg.starts_with("--message-format=") {
+                if let Some((_, format)) = arg.split_once('=') {
+                    json_messages = is_json_format(format);
+                }
+                all.push(arg);
             } else {
                 if !arg.starts_with('-') && sc.is_none() {
                     sc = Some(Subcommand::from(arg.as_ref()));
@@ -66,12 +297,240 @@ pub fn parse(target_list: &TargetList) -> Args {
         .map(|s| bool::from_str(&s).unwrap_or_default())
         .unwrap_or_default();
 
+    let locked = all.iter().any(|a| a == "--locked" || a == "--frozen");
+    let verbose = all
+        .iter()
+        .any(|a| a == "--verbose" || a == "-v" || a == "-vv");
+    let no_run = matches!(sc, Some(Subcommand::Test | Subcommand::Bench))
+        && all
+            .iter()
+            .take_while(|a| *a != "--")
+            .any(|a| a == "--no-run");
+    let timings = all
+        .iter()
+        .take_while(|a| *a != "--")
+        .any(|a| a == "--timings" || a.starts_with("--timings="));
+
+    let color = color_choice(
+        explicit_color.as_deref(),
+        env::var("CARGO_TERM_COLOR").ok().as_deref(),
+        env::var("NO_COLOR").ok().as_deref(),
+    );
+    // the environment doesn't reach `cargo` in the container, so the
+    // preference is passed on as an argument (which `cargo-deb` doesn't take)
+    if let Some(color) = color.as_ref().filter(|_| explicit_color.is_none()) {
+        if sc.map_or(false, |sc| sc.needs_docker() && sc != Subcommand::Deb) {
+            forward_color(&mut all, color);
+        }
+    }
+
     Args {
         all,
         subcommand: sc,
         channel,
-        target,
+        target: targets.last().cloned(),
+        targets,
+        all_targets,
         target_dir,
         docker_in_docker,
+        json_messages,
+        emit_mounts,
+        locked,
+        color,
+        engine_args,
+        verbose,
+        no_run,
+        server,
+        keep_container_on_failure,
+        timings,
+        manifest_path,
+        config_path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_color_forwards_never() {
+        let color = color_choice(None, None, Some("1"));
+        assert_eq!(color.as_deref(), Some("never"));
+
+        let mut all = vec!["run".to_string(), "--".to_string(), "-v".to_string()];
+        forward_color(&mut all, color.as_deref().unwrap());
+        assert_eq!(all, ["run", "--color", "never", "--", "-v"]);
+    }
+
+    fn args(args: &[&str]) -> Args {
+        let target_list = TargetList {
+            triples: vec!["aarch64-unknown-linux-gnu".to_string()],
+        };
+        parse_args(args.iter().map(|a| a.to_string()), &target_list)
+    }
+
+    #[test]
+    fn detect_no_run() {
+        assert!(args(&["test", "--no-run", "--target", "aarch64-unknown-linux-gnu"]).no_run);
+        assert!(args(&["bench", "--no-run"]).no_run);
+        assert!(!args(&["test"]).no_run);
+        assert!(!args(&["build", "--no-run"]).no_run);
+        assert!(!args(&["test", "--", "--no-run"]).no_run);
+    }
+
+    #[test]
+    fn detect_json_messages() {
+        assert!(args(&["build", "--message-format=json"]).json_messages);
+        assert!(args(&["build", "--message-format", "json"]).json_messages);
+        assert!(args(&["build", "--message-format=json-render-diagnostics"]).json_messages);
+        assert!(args(&["build", "--message-format", "short,json-diagnostic-short"]).json_messages);
+        assert!(!args(&["build", "--message-format=short"]).json_messages);
+        assert!(!args(&["build"]).json_messages);
+        assert_eq!(
+            args(&["build", "--message-format", "json"]).all,
+            ["build", "--message-format", "json"]
+        );
+    }
+
+    #[test]
+    fn split_targets() {
+        let multiple = args(&[
+            "build",
+            "--target",
+            "aarch64-unknown-linux-gnu,x86_64-unknown-linux-gnu",
+            "--target=armv7-unknown-linux-gnueabihf",
+            "--",
+            "--target",
+        ]);
+        let per_target = multiple.per_target();
+        assert_eq!(per_target.len(), 3);
+        assert_eq!(
+            per_target[1].target.as_ref().map(Target::triple),
+            Some("x86_64-unknown-linux-gnu")
+        );
+        assert_eq!(
+            per_target[1].all,
+            [
+                "build",
+                "--target",
+                "x86_64-unknown-linux-gnu",
+                "--",
+                "--target"
+            ]
+        );
+
+        let single = args(&["build", "--target", "aarch64-unknown-linux-gnu"]);
+        assert_eq!(single.clone().per_target()[0].all, single.all);
+    }
+
+    #[test]
+    fn expand_all_targets() {
+        let all = args(&["build", "--target", "all", "--", "--target"]);
+        assert!(all.all_targets);
+        assert!(all.targets.is_empty());
+
+        let expanded = all.with_targets(vec![
+            Target::new_built_in("aarch64-unknown-linux-gnu"),
+            Target::new_built_in("armv7-unknown-linux-gnueabihf"),
+        ]);
+        assert!(!expanded.all_targets);
+        assert_eq!(
+            expanded.all,
+            [
+                "build",
+                "--target",
+                "aarch64-unknown-linux-gnu",
+                "--target",
+                "armv7-unknown-linux-gnueabihf",
+                "--",
+                "--target"
+            ]
+        );
+        assert_eq!(expanded.per_target().len(), 2);
+    }
+
+    #[test]
+    fn project_independent_subcommands() {
+        assert!(!args(&["--version"]).needs_project());
+        assert!(!args(&["--help"]).needs_project());
+        assert!(!args(&["install", "ripgrep"]).needs_project());
+        assert!(args(&["build"]).needs_project());
+        assert!(args(&["clean"]).needs_project());
+    }
+
+    #[test]
+    fn server_is_not_forwarded() {
+        let args = args(&["build", "--server", "--release"]);
+        assert!(args.server);
+        assert_eq!(args.all, ["build", "--release"]);
+    }
+
+    #[test]
+    fn subcommand_after_flags() {
+        for (all, sc) in [
+            (
+                &["-v", "shell", "--target", "aarch64-unknown-linux-gnu"][..],
+                Subcommand::Shell,
+            ),
+            (&["--verbose", "deb"][..], Subcommand::Deb),
+            (&["--release", "b"][..], Subcommand::Build),
+        ] {
+            assert_eq!(args(all).subcommand, Some(sc), "{all:?}");
+        }
+    }
+
+    #[test]
+    fn leading_toolchain() {
+        let build = args(&[
+            "+nightly-2024-01-01",
+            "build",
+            "--target",
+            "aarch64-unknown-linux-gnu",
+        ]);
+        assert_eq!(build.channel.as_deref(), Some("nightly-2024-01-01"));
+        assert_eq!(
+            build.all,
+            ["build", "--target", "aarch64-unknown-linux-gnu"]
+        );
+
+        let test = args(&["test", "--", "+not-a-toolchain"]);
+        assert_eq!(test.channel, None);
+        assert_eq!(test.all, ["test", "--", "+not-a-toolchain"]);
+    }
+
+    #[test]
+    fn keep_container_is_not_forwarded() {
+        let args = args(&["test", "--keep-container-on-failure", "--release"]);
+        assert!(args.keep_container_on_failure);
+        assert_eq!(args.all, ["test", "--release"]);
+    }
+
+    #[test]
+    fn timings_are_forwarded() {
+        let args = args(&["build", "--timings=html"]);
+        assert!(args.timings);
+        assert_eq!(args.all, ["build", "--timings=html"]);
+        assert!(!self::args(&["run", "--", "--timings"]).timings);
+    }
+
+    #[test]
+    fn no_run_skips_interpreter() {
+        assert!(args(&["test"]).needs_interpreter());
+        assert!(!args(&["test", "--no-run"]).needs_interpreter());
+        assert!(!args(&["build"]).needs_interpreter());
+    }
+
+    #[test]
+    fn color_precedence() {
+        assert_eq!(
+            color_choice(Some("always"), Some("auto"), Some("1")).as_deref(),
+            Some("always")
+        );
+        assert_eq!(
+            color_choice(None, Some("always"), Some("1")).as_deref(),
+            Some("always")
+        );
+        assert_eq!(color_choice(None, None, Some("")), None);
+        assert_eq!(color_choice(None, None, None), None);
     }
 }
@@ -1,14 +1,19 @@
+use crate::cross_toml::MountMode;
 use crate::{CrossToml, Result, Target, TargetList};
 
 use crate::errors::*;
 use std::collections::HashMap;
 use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
 
+/// The `CROSS_*` variables, read from the process environment, or only from
+/// the map given to [`Environment::new`] in tests
 #[derive(Debug)]
-struct Environment(&'static str, Option<HashMap<&'static str, &'static str>>);
+pub(crate) struct Environment(&'static str, Option<HashMap<&'static str, &'static str>>);
 
 impl Environment {
-    fn new(map: Option<HashMap<&'static str, &'static str>>) -> Self {
+    pub(crate) fn new(map: Option<HashMap<&'static str, &'static str>>) -> Self {
         Environment("CROSS", map)
     }
 
@@ -17,10 +22,10 @@ impl Environment {
     }
 
     fn get_var(&self, name: &str) -> Option<String> {
-        self.1
-            .as_ref()
-            .and_then(|internal_map| internal_map.get(name).map(|v| v.to_string()))
-            .or_else(|| env::var(name).ok())
+        match &self.1 {
+            Some(internal_map) => internal_map.get(name).map(|v| v.to_string()),
+            None => env::var(name).ok(),
+        }
     }
 
     fn target_path(target: &Target, key: &str) -> String {
@@ -40,32 +45,190 @@ impl Environment {
     }
 
     fn xargo(&self, target: &Target) -> Result<(Option<bool>, Option<bool>)> {
-        let (build_xargo, target_xargo) = (
-            self.get_build_var("XARGO"),
-            self.get_target_var(target, "XARGO"),
-        );
-        let build_env = if let Some(value) = build_xargo {
-            Some(value.parse::<bool>().wrap_err_with(|| {
-                format!("error parsing {value} from XARGO environment variable")
-            })?)
-        } else {
-            None
-        };
-        let target_env = if let Some(value) = target_xargo {
-            Some(value.parse::<bool>().wrap_err_with(|| {
-                format!("error parsing {} from XARGO environment variable", value)
-            })?)
-        } else {
-            None
-        };
-
-        Ok((build_env, target_env))
+        self.get_flags_for("XARGO", target)
+    }
+
+    fn qemu_friendly(&self, target: &Target) -> Result<(Option<bool>, Option<bool>)> {
+        self.get_flags_for("QEMU_FRIENDLY", target)
+    }
+
+    fn qemu(&self, target: &Target) -> (Option<String>, Option<String>) {
+        (
+            self.get_build_var("QEMU"),
+            self.get_target_var(target, "QEMU"),
+        )
+    }
+
+    fn memory(&self, target: &Target) -> (Option<String>, Option<String>) {
+        (
+            self.get_build_var("MEMORY"),
+            self.get_target_var(target, "MEMORY"),
+        )
+    }
+
+    fn cpus(&self, target: &Target) -> (Option<String>, Option<String>) {
+        (
+            self.get_build_var("CPUS"),
+            self.get_target_var(target, "CPUS"),
+        )
+    }
+
+    fn static_linking(&self, target: &Target) -> Result<Option<bool>> {
+        self.get_target_flag(target, "STATIC")
+    }
+
+    fn privileged(&self, target: &Target) -> Result<Option<bool>> {
+        self.get_target_flag(target, "PRIVILEGED")
+    }
+
+    fn copy_artifacts(&self) -> Result<Option<bool>> {
+        self.get_build_flag("COPY_ARTIFACTS")
+    }
+
+    fn require_target(&self) -> Result<Option<bool>> {
+        self.get_build_flag("REQUIRE_TARGET")
+    }
+
+    fn build_info(&self) -> Result<Option<bool>> {
+        self.get_build_flag("BUILD_INFO")
+    }
+
+    fn sccache(&self) -> Result<Option<bool>> {
+        self.get_build_flag("SCCACHE")
+    }
+
+    fn incremental(&self) -> Result<Option<bool>> {
+        self.get_build_flag("INCREMENTAL")
+    }
+
+    fn forward_proxy(&self) -> Result<Option<bool>> {
+        self.get_build_flag("FORWARD_PROXY")
+    }
+
+    fn isolate_target_dirs(&self) -> Result<Option<bool>> {
+        self.get_build_flag("ISOLATE_TARGET_DIRS")
+    }
+
+    fn max_image_age(&self) -> Option<String> {
+        self.get_build_var("MAX_IMAGE_AGE")
+    }
+
+    fn image_registry(&self) -> Option<String> {
+        self.get_build_var("IMAGE_REGISTRY")
+    }
+
+    fn cargo(&self) -> Option<String> {
+        self.get_var(&self.build_var_name("CARGO"))
+    }
+
+    fn cargo_home(&self) -> Option<String> {
+        self.get_var(&self.build_var_name("CARGO_HOME"))
+    }
+
+    fn env_file(&self) -> Option<String> {
+        self.get_build_var("ENV_FILE")
+    }
+
+    fn env_file_overrides(&self) -> Result<Option<bool>> {
+        self.get_build_flag("ENV_FILE_OVERRIDES")
+    }
+
+    fn network(&self) -> Option<String> {
+        self.get_var(&self.build_var_name("CONTAINER_NETWORK"))
+    }
+
+    fn hostname(&self) -> Option<String> {
+        self.get_build_var("HOSTNAME")
+    }
+
+    fn workdir(&self) -> Option<String> {
+        self.get_build_var("WORKDIR")
+    }
+
+    fn seccomp(&self) -> Option<String> {
+        self.get_build_var("SECCOMP")
+    }
+
+    fn server_timeout(&self) -> Option<String> {
+        self.get_var(&self.build_var_name("SERVER_TIMEOUT"))
+    }
+
+    fn no_rustup(&self) -> bool {
+        self.get_switch("NO_RUSTUP")
+    }
+
+    fn no_image_check(&self) -> bool {
+        self.get_switch("NO_IMAGE_CHECK")
+    }
+
+    fn timings(&self) -> bool {
+        self.get_switch("TIMINGS")
+    }
+
+    fn no_arch_warning(&self) -> bool {
+        self.get_switch("NO_ARCH_WARNING")
+    }
+
+    fn assume_interpreters_registered(&self) -> bool {
+        self.get_switch("ASSUME_INTERPRETERS_REGISTERED")
+    }
+
+    fn config_allow_lowercase(&self) -> bool {
+        self.get_switch("CONFIG_ALLOW_LOWERCASE")
+    }
+
+    /// Whether `CROSS_{name}` is set, to anything but `0` or `false`
+    fn get_switch(&self, name: &str) -> bool {
+        self.get_var(&self.build_var_name(name))
+            .map_or(false, |value| !matches!(&*value, "" | "0" | "false"))
+    }
+
+    fn registry(&self) -> Result<Option<MountMode>> {
+        self.get_build_var("REGISTRY")
+            .map(|value| value.parse())
+            .transpose()
+    }
+
+    fn source(&self) -> Result<Option<MountMode>> {
+        self.get_build_var("SOURCE")
+            .map(|value| value.parse())
+            .transpose()
+    }
+
+    fn android_api(&self, target: &Target) -> Result<Option<u32>> {
+        self.get_target_var(target, "ANDROID_API")
+            .map(|value| {
+                value.parse::<u32>().wrap_err_with(|| {
+                    format!("error parsing {value} from ANDROID_API environment variable")
+                })
+            })
+            .transpose()
     }
 
     fn image(&self, target: &Target) -> Option<String> {
         self.get_target_var(target, "IMAGE")
     }
 
+    fn toolchain(&self, target: &Target) -> Option<String> {
+        self.get_target_var(target, "TOOLCHAIN")
+    }
+
+    fn linker(&self, target: &Target) -> Option<String> {
+        self.get_target_var(target, "LINKER")
+    }
+
+    fn bench(&self, target: &Target) -> Option<String> {
+        self.get_target_var(target, "BENCH")
+    }
+
+    fn bench_no_warn(&self) -> bool {
+        self.get_switch("BENCH_NO_WARN")
+    }
+
+    fn ar(&self, target: &Target) -> Option<String> {
+        self.get_target_var(target, "AR")
+    }
+
     fn runner(&self, target: &Target) -> Option<String> {
         self.get_target_var(target, "RUNNER")
     }
@@ -82,6 +245,11 @@ impl Environment {
         self.get_build_var("TARGET")
     }
 
+    fn targets(&self) -> Option<Vec<String>> {
+        self.get_build_var("TARGETS")
+            .map(|ref s| split_to_cloned_by_ws(s))
+    }
+
     fn get_values_for(
         &self,
         var: &str,
@@ -97,12 +265,66 @@ impl Environment {
 
         (build_values, target_values)
     }
+
+    fn get_flags_for(&self, var: &str, target: &Target) -> Result<(Option<bool>, Option<bool>)> {
+        Ok((
+            self.get_build_flag(var)?,
+            self.get_target_flag(target, var)?,
+        ))
+    }
+
+    fn get_build_flag(&self, var: &str) -> Result<Option<bool>> {
+        parse_flag(var, self.get_build_var(var))
+    }
+
+    fn get_target_flag(&self, target: &Target, var: &str) -> Result<Option<bool>> {
+        parse_flag(var, self.get_target_var(target, var))
+    }
+}
+
+fn parse_flag(var: &str, value: Option<String>) -> Result<Option<bool>> {
+    value
+        .map(|value| {
+            value
+                .parse::<bool>()
+                .wrap_err_with(|| format!("error parsing {value} from {var} environment variable"))
+        })
+        .transpose()
+}
+
+/// Parses durations such as `30m`, `12h` or `7d`
+fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let (number, unit) = value.split_at(
+        value
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(value.len()),
+    );
+    let number: u64 = number
+        .parse()
+        .wrap_err_with(|| format!("invalid duration `{value}`"))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            eyre::bail!("invalid duration `{value}`, expected a unit of `s`, `m`, `h`, `d` or `w`")
+        }
+    };
+
+    Ok(Duration::from_secs(number * seconds))
 }
 
 fn split_to_cloned_by_ws(string: &str) -> Vec<String> {
     string.split_whitespace().map(String::from).collect()
 }
 
+/// The lowest API level supported by the NDK shipped in the images
+const MIN_ANDROID_API: u32 = 16;
+const MAX_ANDROID_API: u32 = 99;
+
 #[derive(Debug)]
 pub struct Config {
     toml: Option<CrossToml>,
@@ -122,12 +344,9 @@ impl Config {
             for mentioned_target in keys {
                 let mentioned_target_norm = mentioned_target
                     .to_string()
-                    .replace(|c| c == '-' || c == '_', "")
-                    .to_lowercase();
-                let target_norm = target
-                    .to_string()
-                    .replace(|c| c == '-' || c == '_', "")
+                    .replace(['-', '_'], "")
                     .to_lowercase();
+                let target_norm = target.to_string().replace(['-', '_'], "").to_lowercase();
                 if mentioned_target != target && mentioned_target_norm == target_norm {
                     eprintln!("Warning: a target named \"{mentioned_target}\" is mentioned in the Cross configuration, but the current specified target is \"{target}\".");
                     eprintln!(" > Is the target misspelled in the Cross configuration?");
@@ -137,50 +356,349 @@ impl Config {
     }
 
     #[cfg(test)]
-    fn new_with(toml: Option<CrossToml>, env: Environment) -> Self {
+    pub(crate) fn new_with(toml: Option<CrossToml>, env: Environment) -> Self {
         Config { toml, env }
     }
 
+    /// Resolves a setting of the `[build]` and `[target.<triple>]` sections,
+    /// given as `(build, target)` pairs: the target value wins over the build
+    /// one, and at each level the environment wins over `Cross.toml`. The
+    /// getters fall back to their default when this is `None`.
+    fn get_value<T>(
+        &self,
+        env: (Option<T>, Option<T>),
+        toml: impl FnOnce(&CrossToml) -> (Option<T>, Option<T>),
+    ) -> Option<T> {
+        let (env_build, env_target) = env;
+        let (toml_build, toml_target) = self.toml.as_ref().map_or((None, None), toml);
+
+        env_target.or(toml_target).or(env_build).or(toml_build)
+    }
+
+    /// Resolves a setting only found in the `[build]` section
+    fn get_build_value<T>(
+        &self,
+        env: Option<T>,
+        toml: impl FnOnce(&CrossToml) -> Option<T>,
+    ) -> Option<T> {
+        self.get_value((env, None), |t| (toml(t), None))
+    }
+
+    /// Resolves a setting only found in the `[target.<triple>]` sections
+    fn get_target_value<T>(
+        &self,
+        env: Option<T>,
+        toml: impl FnOnce(&CrossToml) -> Option<T>,
+    ) -> Option<T> {
+        self.get_value((None, env), |t| (None, toml(t)))
+    }
+
+    /// Whether `xargo` builds the sysroot. The target setting wins over the
+    /// build setting.
     pub fn xargo(&self, target: &Target) -> Result<Option<bool>> {
-        let (build_xargo, target_xargo) = self.env.xargo(target)?;
-        let (toml_build_xargo, toml_target_xargo) = if let Some(ref toml) = self.toml {
-            toml.xargo(target)
-        } else {
-            (None, None)
-        };
-
-        match (build_xargo, toml_build_xargo) {
-            (xargo @ Some(_), _) => return Ok(xargo),
-            (None, xargo @ Some(_)) => return Ok(xargo),
-            (None, None) => {}
-        };
-
-        match (target_xargo, toml_target_xargo) {
-            (xargo @ Some(_), _) => return Ok(xargo),
-            (None, xargo @ Some(_)) => return Ok(xargo),
-            (None, None) => {}
-        };
-        Ok(None)
+        Ok(self.get_value(self.env.xargo(target)?, |t| t.xargo(target)))
+    }
+
+    /// Whether to relax the container's seccomp profile for targets running
+    /// under QEMU. The target setting wins over the build setting.
+    pub fn qemu_friendly(&self, target: &Target) -> Result<Option<bool>> {
+        Ok(self.get_value(self.env.qemu_friendly(target)?, |t| t.qemu_friendly(target)))
+    }
+
+    /// The tag of the `qemu-user-static` image registering the interpreters.
+    /// The target setting wins over the build setting.
+    pub fn qemu(&self, target: &Target) -> Option<String> {
+        self.get_value(self.env.qemu(target), |t| t.qemu(target))
+    }
+
+    /// The memory limit of the container, a number of bytes with an optional
+    /// `b`, `k`, `m` or `g` suffix. The target setting wins over the build setting.
+    pub fn memory(&self, target: &Target) -> Result<Option<String>> {
+        self.get_value(self.env.memory(target), |t| t.memory(target))
+            .map(|memory| {
+                let number = memory.trim_end_matches(['b', 'k', 'm', 'g', 'B', 'K', 'M', 'G']);
+                match number.parse::<u64>() {
+                    Ok(bytes) if bytes > 0 && number.len() + 1 >= memory.len() => Ok(memory),
+                    _ => Err(eyre::eyre!("invalid memory limit `{memory}`"))
+                        .suggestion("use a number of bytes, optionally followed by `b`, `k`, `m` or `g`, such as `4g`"),
+                }
+            })
+            .transpose()
+    }
+
+    /// The number of CPUs the container may use, which may be fractional.
+    /// The target setting wins over the build setting.
+    pub fn cpus(&self, target: &Target) -> Result<Option<String>> {
+        self.get_value(self.env.cpus(target), |t| t.cpus(target))
+            .map(|cpus| match cpus.parse::<f64>() {
+                Ok(n) if n.is_finite() && n > 0.0 => Ok(cpus),
+                _ => Err(eyre::eyre!("invalid number of CPUs `{cpus}`"))
+                    .suggestion("use a positive number, such as `2` or `1.5`"),
+            })
+            .transpose()
+    }
+
+    pub fn static_linking(&self, target: &Target) -> Result<Option<bool>> {
+        let env_value = self.env.static_linking(target)?;
+        Ok(self.get_target_value(env_value, |t| t.static_linking(target)))
+    }
+
+    /// Whether the container runs with `--privileged`, if set for the target
+    pub fn privileged(&self, target: &Target) -> Result<Option<bool>> {
+        Ok(self.get_target_value(self.env.privileged(target)?, |t| t.privileged(target)))
+    }
+
+    /// Whether a target must be given, instead of building for the host
+    pub fn require_target(&self) -> Result<bool> {
+        Ok(self
+            .get_build_value(self.env.require_target()?, |t| t.require_target())
+            .unwrap_or(false))
+    }
+
+    /// Whether the provenance of successful builds is written next to their
+    /// artifacts
+    pub fn build_info(&self) -> Result<bool> {
+        Ok(self
+            .get_build_value(self.env.build_info()?, |t| t.build_info())
+            .unwrap_or(false))
+    }
+
+    /// Whether `rustc` is wrapped by `sccache` in the container, with the
+    /// cache of the host mounted
+    pub fn sccache(&self) -> Result<bool> {
+        Ok(self
+            .get_build_value(self.env.sccache()?, |t| t.sccache())
+            .unwrap_or(false))
+    }
+
+    /// Whether cargo compiles incrementally in the container, cargo's default
+    /// if unset
+    pub fn incremental(&self) -> Result<Option<bool>> {
+        Ok(self.get_build_value(self.env.incremental()?, |t| t.incremental()))
+    }
+
+    /// Whether the proxy variables of the host are passed into the container
+    pub fn forward_proxy(&self) -> Result<bool> {
+        Ok(self
+            .get_build_value(self.env.forward_proxy()?, |t| t.forward_proxy())
+            .unwrap_or(true))
+    }
+
+    pub fn isolate_target_dirs(&self) -> Result<Option<bool>> {
+        Ok(self.get_build_value(self.env.isolate_target_dirs()?, |t| t.isolate_target_dirs()))
+    }
+
+    pub fn copy_artifacts(&self) -> Result<Option<bool>> {
+        Ok(self.get_build_value(self.env.copy_artifacts()?, |t| t.copy_artifacts()))
+    }
+
+    /// The age after which a cached image is pulled again
+    pub fn max_image_age(&self) -> Result<Option<Duration>> {
+        self.get_build_value(self.env.max_image_age(), |t| t.max_image_age())
+            .map(|value| parse_duration(&value).wrap_err("invalid `max-image-age`"))
+            .transpose()
+    }
+
+    /// How long a persistent container is kept around without builds, from
+    /// `CROSS_SERVER_TIMEOUT`
+    pub fn server_timeout(&self) -> Result<Duration> {
+        match self.env.server_timeout() {
+            Some(value) => parse_duration(&value).wrap_err("invalid `CROSS_SERVER_TIMEOUT`"),
+            None => Ok(Duration::from_secs(30 * 60)),
+        }
+    }
+
+    /// Whether the toolchain is left alone instead of being managed through
+    /// rustup, from `CROSS_NO_RUSTUP`
+    pub fn no_rustup(&self) -> bool {
+        self.env.no_rustup()
+    }
+
+    /// Whether custom images are left unchecked against the toolchain, from
+    /// `CROSS_NO_IMAGE_CHECK`
+    pub fn no_image_check(&self) -> bool {
+        self.env.no_image_check()
+    }
+
+    /// Whether a summary of the build is printed, from `CROSS_TIMINGS`
+    pub fn timings(&self) -> bool {
+        self.env.timings()
+    }
+
+    /// Whether running translated by Rosetta goes without a warning, from
+    /// `CROSS_NO_ARCH_WARNING`
+    pub fn no_arch_warning(&self) -> bool {
+        self.env.no_arch_warning()
+    }
+
+    /// Whether the interpreters are taken as registered, without checking,
+    /// from `CROSS_ASSUME_INTERPRETERS_REGISTERED`
+    pub fn assume_interpreters_registered(&self) -> bool {
+        self.env.assume_interpreters_registered()
+    }
+
+    /// Whether a `cross.toml` is used when there's no `Cross.toml`, from
+    /// `CROSS_CONFIG_ALLOW_LOWERCASE`
+    pub fn config_allow_lowercase(&self) -> bool {
+        self.env.config_allow_lowercase()
+    }
+
+    /// The `cargo` run in the container, and on the host when the build
+    /// doesn't use one, from `CROSS_CARGO`
+    pub fn cargo(&self) -> String {
+        self.get_build_value(self.env.cargo(), |t| t.cargo())
+            .unwrap_or_else(|| "cargo".to_string())
+    }
+
+    /// Where the cargo home is mounted inside the container
+    pub fn cargo_home(&self) -> Result<PathBuf> {
+        let cargo_home = self
+            .get_build_value(self.env.cargo_home(), |t| t.cargo_home())
+            .unwrap_or_else(|| "/cargo".to_string());
+        // the container is always Linux, whatever the host
+        if !cargo_home.starts_with('/') {
+            return Err(eyre::eyre!(
+                "`cargo-home` must be an absolute path in the container, got `{cargo_home}`"
+            ))
+            .with_suggestion(|| format!("did you mean `/{cargo_home}`?"));
+        }
+        Ok(PathBuf::from(cargo_home))
+    }
+
+    /// The env file passed into the container, relative to the project root
+    pub fn env_file(&self) -> Option<String> {
+        self.get_build_value(self.env.env_file(), |t| t.env_file())
+    }
+
+    /// Whether the env file wins over variables set on the host
+    pub fn env_file_overrides(&self) -> Result<bool> {
+        Ok(self
+            .get_build_value(self.env.env_file_overrides()?, |t| t.env_file_overrides())
+            .unwrap_or(false))
+    }
+
+    /// The seccomp profile of the container, `unconfined` or the path of a
+    /// profile, instead of the engine's default
+    pub fn seccomp(&self) -> Option<String> {
+        self.get_build_value(self.env.seccomp(), |t| t.seccomp())
+    }
+
+    /// The network the container is connected to, the engine's default if unset
+    pub fn network(&self) -> Option<String> {
+        self.get_build_value(self.env.network(), |t| t.network())
+    }
+
+    /// The hostname of the container, a random one from the engine if unset
+    pub fn hostname(&self) -> Result<Option<String>> {
+        self.get_build_value(self.env.hostname(), |t| t.hostname())
+            .map(|hostname| {
+                let valid = hostname.split('.').all(|label| {
+                    !label.is_empty()
+                        && label.len() <= 63
+                        && !label.starts_with('-')
+                        && !label.ends_with('-')
+                        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                });
+                if valid && hostname.len() <= 253 {
+                    Ok(hostname)
+                } else {
+                    Err(eyre::eyre!("invalid hostname `{hostname}`"))
+                        .suggestion("use letters, digits and hyphens, in labels separated by dots")
+                }
+            })
+            .transpose()
+    }
+
+    /// The directory the build runs in, relative to the project, instead of
+    /// the one `cross` is run from
+    pub fn workdir(&self) -> Option<String> {
+        self.get_build_value(self.env.workdir(), |t| t.workdir())
+    }
+
+    /// How the cargo registry and git database are mounted
+    pub fn registry(&self) -> Result<MountMode> {
+        Ok(self
+            .get_build_value(self.env.registry()?, |t| t.registry())
+            .unwrap_or(MountMode::Rw))
+    }
+
+    /// How the project's source tree is mounted
+    pub fn source(&self) -> Result<MountMode> {
+        Ok(self
+            .get_build_value(self.env.source()?, |t| t.source())
+            .unwrap_or(MountMode::Rw))
+    }
+
+    /// The Android API level, validated against the levels the NDK supports
+    pub fn android_api(&self, target: &Target) -> Result<Option<u32>> {
+        let api = self.get_target_value(self.env.android_api(target)?, |t| t.android_api(target));
+
+        if let Some(api) = api {
+            if !target.is_android() {
+                eyre::bail!("`android-api` is set for {target}, which is not an Android target");
+            }
+            if !(MIN_ANDROID_API..=MAX_ANDROID_API).contains(&api) {
+                eyre::bail!(
+                    "invalid Android API level {api} for {target}, \
+                     expected a level between {MIN_ANDROID_API} and {MAX_ANDROID_API}"
+                );
+            }
+        }
+
+        Ok(api)
     }
 
     pub fn image(&self, target: &Target) -> Result<Option<String>> {
-        let env_value = self.env.image(target);
-        if let Some(env_value) = env_value {
-            return Ok(Some(env_value));
+        Ok(self.get_target_value(self.env.image(target), |t| t.image(target)))
+    }
+
+    /// The registry the default images are pulled from, instead of `ghcr.io/cross-rs`
+    pub fn image_registry(&self) -> Option<String> {
+        self.get_build_value(self.env.image_registry(), |t| t.image_registry())
+    }
+
+    /// The toolchain channel to build `target` with, unless given on the command line
+    pub fn toolchain(&self, target: &Target) -> Option<String> {
+        self.get_target_value(self.env.toolchain(target), |t| t.toolchain(target))
+    }
+
+    /// The linker of `target`: `zig`, which `cross` sets up, or the linker
+    /// cargo is told to use
+    pub fn linker(&self, target: &Target) -> Option<String> {
+        self.get_target_value(self.env.linker(target), |t| t.linker(target))
+    }
+
+    /// Whether the benchmarks of `target` are skipped when they'd run under
+    /// emulation, with `bench = "skip"`, rather than run (`"run"`)
+    pub fn skip_emulated_bench(&self, target: &Target) -> Result<bool> {
+        let bench = self.get_target_value(self.env.bench(target), |t| t.bench(target));
+        match bench.as_deref() {
+            None | Some("run") => Ok(false),
+            Some("skip") => Ok(true),
+            Some(other) => {
+                eyre::bail!("invalid `bench` for {target}: `{other}`, expected `run` or `skip`")
+            }
         }
-        self.toml.as_ref().map_or(Ok(None), |t| Ok(t.image(target)))
+    }
+
+    /// Whether benchmarks run under emulation go without a warning, from
+    /// `CROSS_BENCH_NO_WARN`
+    pub fn bench_no_warn(&self) -> bool {
+        self.env.bench_no_warn()
+    }
+
+    /// The archiver cargo is told to use for `target`
+    pub fn ar(&self, target: &Target) -> Option<String> {
+        self.get_target_value(self.env.ar(target), |t| t.ar(target))
     }
 
     pub fn runner(&self, target: &Target) -> Result<Option<String>> {
-        let env_value = self.env.runner(target);
-        if let Some(env_value) = env_value {
-            return Ok(Some(env_value));
-        }
-        self.toml
-            .as_ref()
-            .map_or(Ok(None), |t| Ok(t.runner(target)))
+        Ok(self.get_target_value(self.env.runner(target), |t| t.runner(target)))
     }
 
+    /// The variables passed through to the container. Unlike the other
+    /// settings, the target list adds to the build list rather than replacing
+    /// it, and at each level the environment replaces `Cross.toml`.
     pub fn env_passthrough(&self, target: &Target) -> Result<Vec<String>> {
         let (env_build, env_target) = self.env.passthrough(target);
 
@@ -193,6 +711,30 @@ impl Config {
         Ok(collected)
     }
 
+    /// Extra `docker run` arguments, the build ones first
+    pub fn docker_args(&self, target: &Target) -> Vec<String> {
+        self.toml
+            .as_ref()
+            .map_or(Vec::new(), |t| t.docker_args(target))
+    }
+
+    /// The command wrapping `cargo` in the container, followed by `--`
+    pub fn runner_wrapper(&self, target: &Target) -> Vec<String> {
+        self.toml
+            .as_ref()
+            .map_or(Vec::new(), |t| t.runner_wrapper(target))
+    }
+
+    /// The commands run in the container after a successful `cargo build`,
+    /// with `{bin}` standing for each executable built
+    pub fn post_build(&self, target: &Target) -> Vec<String> {
+        self.toml
+            .as_ref()
+            .map_or(Vec::new(), |t| t.post_build(target))
+    }
+
+    /// The variables naming volumes mounted in the container, the target
+    /// list adding to the build list like [`Config::env_passthrough`]
     pub fn env_volumes(&self, target: &Target) -> Result<Vec<String>> {
         let (env_build, env_target) = self.env.volumes(target);
         let toml_getter = || self.toml.as_ref().map(|t| t.env_volumes_build());
@@ -205,12 +747,20 @@ impl Config {
     }
 
     pub fn target(&self, target_list: &TargetList) -> Option<Target> {
-        if let Some(env_value) = self.env.target() {
-            return Some(Target::from(&env_value, target_list));
-        }
-        self.toml
-            .as_ref()
-            .and_then(|t| t.default_target(target_list))
+        let env_value = self.env.target().map(|t| Target::from(&t, target_list));
+        self.get_build_value(env_value, |t| t.default_target(target_list))
+    }
+
+    /// The targets `--target all` builds, from `build.targets`
+    pub fn targets(&self, target_list: &TargetList) -> Vec<Target> {
+        let env_value = self.env.targets().map(|targets| {
+            targets
+                .iter()
+                .map(|t| Target::from(t, target_list))
+                .collect()
+        });
+        self.get_build_value(env_value, |t| Some(t.targets(target_list)))
+            .unwrap_or_default()
     }
 
     fn sum_of_env_toml_values(
@@ -221,7 +771,7 @@ impl Config {
         if let Some(mut vars) = env_values {
             collect.append(&mut vars);
         } else if let Some(toml_values) = toml_getter() {
-            collect.extend(toml_values.into_iter());
+            collect.extend(toml_values);
         }
 
         Ok(collect)
@@ -293,6 +843,38 @@ mod tests {
             )
         }
 
+        #[test]
+        pub fn no_rustup() {
+            for (value, expected) in [("1", true), ("true", true), ("0", false), ("", false)] {
+                let mut map = std::collections::HashMap::new();
+                map.insert("CROSS_NO_RUSTUP", value);
+                assert_eq!(Environment::new(Some(map)).no_rustup(), expected);
+            }
+        }
+
+        #[test]
+        pub fn assume_interpreters_registered() {
+            let mut map = std::collections::HashMap::new();
+            let config = Config::new_with(None, Environment::new(Some(map.clone())));
+            assert!(!config.assume_interpreters_registered());
+
+            map.insert("CROSS_ASSUME_INTERPRETERS_REGISTERED", "1");
+            let config = Config::new_with(None, Environment::new(Some(map)));
+            assert!(config.assume_interpreters_registered());
+        }
+
+        #[test]
+        pub fn parse_durations() -> Result<()> {
+            assert_eq!(parse_duration("45s")?, Duration::from_secs(45));
+            assert_eq!(parse_duration("12h")?, Duration::from_secs(12 * 60 * 60));
+            assert_eq!(parse_duration("7d")?, Duration::from_secs(7 * 24 * 60 * 60));
+            assert!(parse_duration("7").is_err());
+            assert!(parse_duration("d").is_err());
+            assert!(parse_duration("7y").is_err());
+
+            Ok(())
+        }
+
         #[test]
         pub fn collect_passthrough() {
             let mut map = std::collections::HashMap::new();
@@ -347,12 +929,190 @@ mod tests {
         }
 
         #[test]
-        pub fn env_target_and_toml_build_xargo_then_use_toml() -> Result<()> {
+        pub fn env_target_and_toml_build_xargo_then_use_env() -> Result<()> {
             let mut map = HashMap::new();
             map.insert("CROSS_TARGET_AARCH64_UNKNOWN_LINUX_GNU_XARGO", "true");
             let env = Environment::new(Some(map));
             let config = Config::new_with(Some(toml(TOML_BUILD_XARGO_FALSE)?), env);
-            assert!(matches!(config.xargo(&target()), Ok(Some(false))));
+            assert!(matches!(config.xargo(&target()), Ok(Some(true))));
+
+            Ok(())
+        }
+
+        #[test]
+        pub fn target_inherits_from_build() -> Result<()> {
+            let both = r#"
+                [build]
+                xargo = true
+                qemu = "build"
+                memory = "1g"
+                cpus = "1"
+                [target.aarch64-unknown-linux-gnu]
+                xargo = false
+                qemu = "target"
+                memory = "2g"
+                cpus = "2"
+                "#;
+            let config = Config::new_with(Some(toml(both)?), Environment::new(None));
+            assert_eq!(config.xargo(&target())?, Some(false));
+            assert_eq!(config.qemu(&target()).as_deref(), Some("target"));
+            assert_eq!(config.memory(&target())?.as_deref(), Some("2g"));
+            assert_eq!(config.cpus(&target())?.as_deref(), Some("2"));
+
+            // other targets fall back to the build values
+            let other = Target::new_built_in("x86_64-unknown-linux-gnu");
+            assert_eq!(config.xargo(&other)?, Some(true));
+            assert_eq!(config.qemu(&other).as_deref(), Some("build"));
+            assert_eq!(config.memory(&other)?.as_deref(), Some("1g"));
+            assert_eq!(config.cpus(&other)?.as_deref(), Some("1"));
+
+            // the build environment doesn't override the target values
+            let mut map = HashMap::new();
+            map.insert("CROSS_BUILD_XARGO", "true");
+            map.insert("CROSS_BUILD_QEMU", "env");
+            map.insert("CROSS_BUILD_MEMORY", "3g");
+            map.insert("CROSS_BUILD_CPUS", "3");
+            let config = Config::new_with(Some(toml(both)?), Environment::new(Some(map)));
+            assert_eq!(config.xargo(&target())?, Some(false));
+            assert_eq!(config.qemu(&target()).as_deref(), Some("target"));
+            assert_eq!(config.memory(&target())?.as_deref(), Some("2g"));
+            assert_eq!(config.cpus(&target())?.as_deref(), Some("2"));
+            assert_eq!(config.xargo(&other)?, Some(true));
+            assert_eq!(config.qemu(&other).as_deref(), Some("env"));
+            assert_eq!(config.memory(&other)?.as_deref(), Some("3g"));
+            assert_eq!(config.cpus(&other)?.as_deref(), Some("3"));
+
+            // nothing set leaves the default to the caller
+            let config = Config::new_with(None, Environment::new(None));
+            assert_eq!(config.xargo(&target())?, None);
+            assert_eq!(config.qemu(&target()), None);
+            assert_eq!(config.memory(&target())?, None);
+            assert_eq!(config.cpus(&target())?, None);
+
+            Ok(())
+        }
+
+        #[test]
+        pub fn target_only_values() -> Result<()> {
+            let content = r#"
+                [target.aarch64-unknown-linux-gnu]
+                image = "toml-image"
+                runner = "qemu-user"
+                "#;
+            let config = Config::new_with(Some(toml(content)?), Environment::new(None));
+            assert_eq!(config.image(&target())?.as_deref(), Some("toml-image"));
+            assert_eq!(config.runner(&target())?.as_deref(), Some("qemu-user"));
+
+            let mut map = HashMap::new();
+            map.insert("CROSS_TARGET_AARCH64_UNKNOWN_LINUX_GNU_IMAGE", "env-image");
+            let config = Config::new_with(Some(toml(content)?), Environment::new(Some(map)));
+            assert_eq!(config.image(&target())?.as_deref(), Some("env-image"));
+            assert_eq!(config.runner(&target())?.as_deref(), Some("qemu-user"));
+            let other = Target::new_built_in("x86_64-unknown-linux-gnu");
+            assert_eq!(config.image(&other)?, None);
+
+            Ok(())
+        }
+
+        #[test]
+        pub fn qemu_friendly_target_over_build() -> Result<()> {
+            let mut map = HashMap::new();
+            map.insert("CROSS_BUILD_QEMU_FRIENDLY", "true");
+            let env = Environment::new(Some(map));
+            let config = Config::new_with(Some(toml(TOML_TARGET_QEMU_FRIENDLY_FALSE)?), env);
+            assert!(matches!(config.qemu_friendly(&target()), Ok(Some(false))));
+
+            Ok(())
+        }
+
+        #[test]
+        pub fn android_api_out_of_range() -> Result<()> {
+            let toml = toml(
+                r#"
+                [target.aarch64-linux-android]
+                android-api = 3
+                "#,
+            )?;
+            let config = Config::new_with(Some(toml), Environment::new(None));
+            let target = Target::new_built_in("aarch64-linux-android");
+            assert!(config.android_api(&target).is_err());
+
+            Ok(())
+        }
+
+        #[test]
+        pub fn require_target_env_over_toml() -> Result<()> {
+            let required = toml(
+                r#"
+                [build]
+                require-target = true
+                "#,
+            )?;
+            let mut map = HashMap::new();
+            map.insert("CROSS_BUILD_REQUIRE_TARGET", "false");
+            let config = Config::new_with(Some(required), Environment::new(Some(map)));
+            assert!(!config.require_target()?);
+
+            let config = Config::new_with(None, Environment::new(None));
+            assert!(!config.require_target()?);
+
+            Ok(())
+        }
+
+        #[test]
+        pub fn cargo_home_must_be_absolute() -> Result<()> {
+            let relative = toml(
+                r#"
+                [build]
+                cargo-home = "opt/cargo"
+                "#,
+            )?;
+            let config = Config::new_with(Some(relative), Environment::new(None));
+            assert!(config.cargo_home().is_err());
+
+            let mut map = HashMap::new();
+            map.insert("CROSS_CARGO_HOME", "/opt/cargo");
+            let toml = toml(
+                r#"
+                [build]
+                cargo-home = "/usr/local/cargo"
+                "#,
+            )?;
+            let config = Config::new_with(Some(toml), Environment::new(Some(map)));
+            assert_eq!(config.cargo_home()?, PathBuf::from("/opt/cargo"));
+
+            Ok(())
+        }
+
+        #[test]
+        pub fn toolchain_per_target() -> Result<()> {
+            let toml = toml(
+                r#"
+                [target.aarch64-unknown-linux-gnu]
+                toolchain = "nightly-2024-01-01"
+                "#,
+            )?;
+            let config = Config::new_with(Some(toml), Environment::new(None));
+            assert_eq!(
+                config
+                    .toolchain(&Target::new_built_in("aarch64-unknown-linux-gnu"))
+                    .as_deref(),
+                Some("nightly-2024-01-01")
+            );
+            assert_eq!(
+                config.toolchain(&Target::new_built_in("x86_64-unknown-linux-gnu")),
+                None
+            );
+
+            Ok(())
+        }
+
+        #[test]
+        pub fn android_api_on_non_android_target() -> Result<()> {
+            let mut map = HashMap::new();
+            map.insert("CROSS_TARGET_AARCH64_UNKNOWN_LINUX_GNU_ANDROID_API", "28");
+            let config = Config::new_with(None, Environment::new(Some(map)));
+            assert!(config.android_api(&target()).is_err());
 
             Ok(())
         }
@@ -363,7 +1123,7 @@ mod tests {
             map.insert("CROSS_BUILD_ENV_VOLUMES", "VOLUME1 VOLUME2");
             let env = Environment::new(Some(map));
             let config = Config::new_with(Some(toml(TOML_BUILD_VOLUMES)?), env);
-            let expected = vec!["VOLUME1".to_string(), "VOLUME2".into()];
+            let expected = ["VOLUME1".to_string(), "VOLUME2".into()];
 
             let result = config.env_volumes(&target()).unwrap();
             assert!(result.len() == 2);
@@ -378,7 +1138,7 @@ mod tests {
             let map = HashMap::new();
             let env = Environment::new(Some(map));
             let config = Config::new_with(Some(toml(TOML_BUILD_VOLUMES)?), env);
-            let expected = vec!["VOLUME3".to_string(), "VOLUME4".into()];
+            let expected = ["VOLUME3".to_string(), "VOLUME4".into()];
 
             let result = config.env_volumes(&target()).unwrap();
             assert!(result.len() == 2);
@@ -392,7 +1152,7 @@ mod tests {
         pub fn no_env_and_no_toml_default_target_then_none() -> Result<()> {
             let config = Config::new_with(None, Environment::new(None));
             let config_target = config.target(&target_list());
-            assert!(matches!(config_target, None));
+            assert!(config_target.is_none());
 
             Ok(())
         }
@@ -427,6 +1187,33 @@ mod tests {
             Ok(())
         }
 
+        #[test]
+        pub fn env_targets_over_toml() -> Result<()> {
+            let toml = toml(
+                r#"
+                [build]
+                targets = ["aarch64-unknown-linux-gnu", "armv7-unknown-linux-gnueabihf"]
+                "#,
+            )?;
+            let config = Config::new_with(Some(toml), Environment::new(None));
+            let targets = config.targets(&target_list());
+            assert_eq!(
+                targets.iter().map(Target::triple).collect::<Vec<_>>(),
+                ["aarch64-unknown-linux-gnu", "armv7-unknown-linux-gnueabihf"]
+            );
+
+            let mut map = HashMap::new();
+            map.insert("CROSS_BUILD_TARGETS", "x86_64-unknown-linux-gnu");
+            let config = Config::new_with(None, Environment::new(Some(map)));
+            let targets = config.targets(&target_list());
+            assert_eq!(
+                targets.iter().map(Target::triple).collect::<Vec<_>>(),
+                ["x86_64-unknown-linux-gnu"]
+            );
+
+            Ok(())
+        }
+
         static TOML_BUILD_XARGO_FALSE: &str = r#"
     [build]
     xargo = false
@@ -435,6 +1222,11 @@ mod tests {
         static TOML_TARGET_XARGO_FALSE: &str = r#"
     [target.aarch64-unknown-linux-gnu]
     xargo = false
+    "#;
+
+        static TOML_TARGET_QEMU_FRIENDLY_FALSE: &str = r#"
+    [target.aarch64-unknown-linux-gnu]
+    qemu-friendly = false
     "#;
 
         static TOML_BUILD_VOLUMES: &str = r#"
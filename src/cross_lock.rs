@@ -0,0 +1,165 @@
+//! `Cross.lock`, which records the image used for each target by the last
+//! successful build, so it can be pinned by digest with `--locked`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::errors::*;
+use crate::file;
+use crate::Target;
+use serde::{Deserialize, Serialize};
+
+/// The image a target was last built with
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct LockedImage {
+    pub image: String,
+    pub digest: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct CrossLock {
+    #[serde(default, rename = "target")]
+    targets: BTreeMap<String, LockedImage>,
+}
+
+impl CrossLock {
+    /// Reads the lockfile at `path`, which may not exist yet
+    pub fn read(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(CrossLock::default());
+        }
+
+        let content = file::read(path)?;
+        toml::from_str(&content)
+            .wrap_err_with(|| format!("failed to parse file `{}` as TOML", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = format!(
+            "# This file is generated by `cross`, and is not meant to be edited by hand.\n{}",
+            toml::to_string(self)?
+        );
+        fs::write(path, content).wrap_err_with(|| format!("couldn't write {}", path.display()))
+    }
+
+    pub fn get(&self, target: &Target) -> Option<&LockedImage> {
+        self.targets.get(target.triple())
+    }
+
+    pub fn insert(&mut self, target: &Target, image: &str, digest: &str) {
+        self.targets.insert(
+            target.triple().to_string(),
+            LockedImage {
+                image: image.to_string(),
+                digest: digest.to_string(),
+            },
+        );
+    }
+
+    /// The reference of `image` pinned to its locked digest, for `target`.
+    ///
+    /// `current` is the digest `image` resolves to now, if it's known.
+    pub fn pinned(
+        &self,
+        target: &Target,
+        image: &str,
+        current: Option<&str>,
+    ) -> Result<Option<String>> {
+        let locked = match self.get(target) {
+            Some(locked) => locked,
+            None => return Ok(None),
+        };
+
+        if locked.image != image {
+            return Err(eyre::eyre!(
+                "the image for {target} is `{image}`, but `Cross.lock` has `{}`",
+                locked.image
+            ))
+            .suggestion("run without `--locked` to update `Cross.lock`");
+        }
+        if let Some(current) = current.filter(|&current| current != locked.digest) {
+            return Err(eyre::eyre!(
+                "`{image}` now resolves to {current}, but `Cross.lock` has {}",
+                locked.digest
+            ))
+            .suggestion("run without `--locked` to update `Cross.lock`");
+        }
+
+        Ok(Some(format!(
+            "{}@{}",
+            repository(&locked.image),
+            locked.digest
+        )))
+    }
+}
+
+/// The image reference without its tag
+fn repository(image: &str) -> &str {
+    match image.rsplit_once(':') {
+        // a colon before the last slash separates the registry's port
+        Some((repository, tag)) if !tag.contains('/') => repository,
+        _ => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE: &str = "ghcr.io/cross-rs/aarch64-unknown-linux-gnu:main";
+    const DIGEST: &str = "sha256:0123456789abcdef";
+
+    fn lock() -> CrossLock {
+        let mut lock = CrossLock::default();
+        lock.insert(
+            &Target::new_built_in("aarch64-unknown-linux-gnu"),
+            IMAGE,
+            DIGEST,
+        );
+        lock
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let content = toml::to_string(&lock())?;
+        assert!(content.contains("[target.aarch64-unknown-linux-gnu]"));
+        assert_eq!(toml::from_str::<CrossLock>(&content)?, lock());
+
+        Ok(())
+    }
+
+    #[test]
+    fn pin_locked_digest() -> Result<()> {
+        let target = Target::new_built_in("aarch64-unknown-linux-gnu");
+        assert_eq!(
+            lock().pinned(&target, IMAGE, Some(DIGEST))?.as_deref(),
+            Some("ghcr.io/cross-rs/aarch64-unknown-linux-gnu@sha256:0123456789abcdef")
+        );
+        assert!(lock().pinned(&target, IMAGE, None)?.is_some());
+
+        let other = Target::new_built_in("x86_64-unknown-linux-gnu");
+        assert_eq!(lock().pinned(&other, IMAGE, None)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn changed_digest_is_an_error() {
+        let target = Target::new_built_in("aarch64-unknown-linux-gnu");
+        assert!(lock()
+            .pinned(&target, IMAGE, Some("sha256:fedcba9876543210"))
+            .is_err());
+        assert!(lock().pinned(&target, "my/image:latest", None).is_err());
+    }
+
+    #[test]
+    fn strip_tag() {
+        assert_eq!(repository("ubuntu:20.04"), "ubuntu");
+        assert_eq!(repository("localhost:5000/image"), "localhost:5000/image");
+        assert_eq!(
+            repository("localhost:5000/image:latest"),
+            "localhost:5000/image"
+        );
+    }
+}
@@ -2,8 +2,36 @@
 
 use crate::errors::*;
 use crate::{Target, TargetList};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+/// Whether a directory is mounted read-only or read-write
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum MountMode {
+    Ro,
+    Rw,
+}
+
+impl MountMode {
+    pub fn is_read_only(self) -> bool {
+        self == MountMode::Ro
+    }
+}
+
+impl FromStr for MountMode {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ro" => Ok(MountMode::Ro),
+            "rw" => Ok(MountMode::Rw),
+            _ => eyre::bail!("invalid mount mode `{s}`, expected `ro` or `rw`"),
+        }
+    }
+}
 
 /// Environment configuration
 #[derive(Debug, Deserialize, PartialEq, Default)]
@@ -22,16 +50,61 @@ pub struct CrossBuildConfig {
     env: CrossEnvConfig,
     xargo: Option<bool>,
     default_target: Option<String>,
+    #[serde(default)]
+    targets: Vec<String>,
+    qemu_friendly: Option<bool>,
+    qemu: Option<String>,
+    memory: Option<String>,
+    cpus: Option<String>,
+    copy_artifacts: Option<bool>,
+    max_image_age: Option<String>,
+    image_registry: Option<String>,
+    registry: Option<MountMode>,
+    isolate_target_dirs: Option<bool>,
+    cargo_home: Option<String>,
+    #[serde(default)]
+    docker_args: Vec<String>,
+    network: Option<String>,
+    hostname: Option<String>,
+    forward_proxy: Option<bool>,
+    incremental: Option<bool>,
+    sccache: Option<bool>,
+    workdir: Option<String>,
+    env_file: Option<String>,
+    env_file_overrides: Option<bool>,
+    seccomp: Option<String>,
+    require_target: Option<bool>,
+    build_info: Option<bool>,
+    cargo: Option<String>,
+    source: Option<MountMode>,
 }
 
 /// Target configuration
 #[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
 pub struct CrossTargetConfig {
     xargo: Option<bool>,
     image: Option<String>,
     runner: Option<String>,
     #[serde(default)]
     env: CrossEnvConfig,
+    qemu_friendly: Option<bool>,
+    qemu: Option<String>,
+    memory: Option<String>,
+    cpus: Option<String>,
+    r#static: Option<bool>,
+    android_api: Option<u32>,
+    privileged: Option<bool>,
+    #[serde(default)]
+    docker_args: Vec<String>,
+    #[serde(default)]
+    runner_wrapper: Vec<String>,
+    #[serde(default)]
+    post_build: Vec<String>,
+    toolchain: Option<String>,
+    linker: Option<String>,
+    ar: Option<String>,
+    bench: Option<String>,
 }
 
 /// Cross configuration
@@ -46,6 +119,21 @@ pub struct CrossToml {
 impl CrossToml {
     /// Parses the [`CrossToml`] from a string
     pub fn parse(toml_str: &str) -> Result<(Self, BTreeSet<String>)> {
+        let (cfg, unused) = Self::parse_quietly(toml_str)?;
+
+        if !unused.is_empty() {
+            eprintln!(
+                "Warning: found unused key(s) in Cross configuration:\n > {}",
+                unused.clone().into_iter().collect::<Vec<_>>().join(", ")
+            );
+        }
+
+        Ok((cfg, unused))
+    }
+
+    /// Parses the [`CrossToml`] from a string, returning the unused keys
+    /// without warning about them
+    fn parse_quietly(toml_str: &str) -> Result<(Self, BTreeSet<String>)> {
         let tomld = &mut toml::Deserializer::new(toml_str);
 
         let mut unused = BTreeSet::new();
@@ -54,14 +142,36 @@ impl CrossToml {
             unused.insert(path.to_string());
         })?;
 
-        if !unused.is_empty() {
-            eprintln!(
-                "Warning: found unused key(s) in Cross configuration:\n > {}",
-                unused.clone().into_iter().collect::<Vec<_>>().join(", ")
-            );
+        Ok((cfg, unused))
+    }
+
+    /// Parses the [`CrossToml`] from a string, and reports the problems that
+    /// parsing alone lets through: unknown keys and targets, and options
+    /// that don't go together
+    pub fn check(toml_str: &str, target_list: &TargetList) -> Result<(Self, Vec<Issue>)> {
+        let (cfg, unused) = Self::parse_quietly(toml_str)?;
+
+        let mut issues: Vec<_> = unused
+            .into_iter()
+            .map(|key| Issue::new(toml_str, key, "unknown key".to_string()))
+            .collect();
+
+        let mut targets: Vec<_> = cfg.targets.keys().collect();
+        targets.sort_by_key(|target| target.triple());
+        for target in targets {
+            if !target_list.contains(target.triple()) {
+                issues.push(Issue::new(
+                    toml_str,
+                    format!("target.{target}"),
+                    format!(
+                        "`{target}` is not a built-in target, so it needs a target \
+                         specification, in `RUST_TARGET_PATH` for instance"
+                    ),
+                ));
+            }
         }
 
-        Ok((cfg, unused))
+        Ok((cfg, issues))
     }
 
     /// Returns the `target.{}.image` part of `Cross.toml`
@@ -69,6 +179,26 @@ impl CrossToml {
         self.get_target(target).and_then(|t| t.image.clone())
     }
 
+    /// Returns the `target.{}.toolchain` part of `Cross.toml`
+    pub fn toolchain(&self, target: &Target) -> Option<String> {
+        self.get_target(target).and_then(|t| t.toolchain.clone())
+    }
+
+    /// Returns the `target.{}.linker` part of `Cross.toml`
+    pub fn linker(&self, target: &Target) -> Option<String> {
+        self.get_target(target).and_then(|t| t.linker.clone())
+    }
+
+    /// Returns the `target.{}.bench` part of `Cross.toml`
+    pub fn bench(&self, target: &Target) -> Option<String> {
+        self.get_target(target).and_then(|t| t.bench.clone())
+    }
+
+    /// Returns the `target.{}.ar` part of `Cross.toml`
+    pub fn ar(&self, target: &Target) -> Option<String> {
+        self.get_target(target).and_then(|t| t.ar.clone())
+    }
+
     /// Returns the `target.{}.runner` part of `Cross.toml`
     pub fn runner(&self, target: &Target) -> Option<String> {
         self.get_target(target).and_then(|t| t.runner.clone())
@@ -82,6 +212,103 @@ impl CrossToml {
         (build_xargo, target_xargo)
     }
 
+    /// Returns the `build.qemu-friendly` or the `target.{}.qemu-friendly` part of `Cross.toml`
+    pub fn qemu_friendly(&self, target: &Target) -> (Option<bool>, Option<bool>) {
+        let build_qemu_friendly = self.build.qemu_friendly;
+        let target_qemu_friendly = self.get_target(target).and_then(|t| t.qemu_friendly);
+
+        (build_qemu_friendly, target_qemu_friendly)
+    }
+
+    /// Returns the `build.qemu` or the `target.{}.qemu` part of `Cross.toml`
+    pub fn qemu(&self, target: &Target) -> (Option<String>, Option<String>) {
+        let build_qemu = self.build.qemu.clone();
+        let target_qemu = self.get_target(target).and_then(|t| t.qemu.clone());
+
+        (build_qemu, target_qemu)
+    }
+
+    /// Returns the `build.memory` or the `target.{}.memory` part of `Cross.toml`
+    pub fn memory(&self, target: &Target) -> (Option<String>, Option<String>) {
+        let build_memory = self.build.memory.clone();
+        let target_memory = self.get_target(target).and_then(|t| t.memory.clone());
+
+        (build_memory, target_memory)
+    }
+
+    /// Returns the `build.cpus` or the `target.{}.cpus` part of `Cross.toml`
+    pub fn cpus(&self, target: &Target) -> (Option<String>, Option<String>) {
+        let build_cpus = self.build.cpus.clone();
+        let target_cpus = self.get_target(target).and_then(|t| t.cpus.clone());
+
+        (build_cpus, target_cpus)
+    }
+
+    /// Returns the `build.copy-artifacts` part of `Cross.toml`
+    pub fn copy_artifacts(&self) -> Option<bool> {
+        self.build.copy_artifacts
+    }
+
+    /// Returns the `build.max-image-age` part of `Cross.toml`
+    pub fn max_image_age(&self) -> Option<String> {
+        self.build.max_image_age.clone()
+    }
+
+    /// Returns the `build.image-registry` part of `Cross.toml`
+    pub fn image_registry(&self) -> Option<String> {
+        self.build.image_registry.clone()
+    }
+
+    /// Returns the `build.source` part of `Cross.toml`
+    pub fn source(&self) -> Option<MountMode> {
+        self.build.source
+    }
+
+    /// Returns the `build.registry` part of `Cross.toml`
+    pub fn registry(&self) -> Option<MountMode> {
+        self.build.registry
+    }
+
+    /// Returns the `build.require-target` part of `Cross.toml`
+    pub fn require_target(&self) -> Option<bool> {
+        self.build.require_target
+    }
+
+    /// Returns the `build.cargo` part of `Cross.toml`
+    pub fn cargo(&self) -> Option<String> {
+        self.build.cargo.clone()
+    }
+
+    /// Returns the `build.build-info` part of `Cross.toml`
+    pub fn build_info(&self) -> Option<bool> {
+        self.build.build_info
+    }
+
+    /// Returns the `build.isolate-target-dirs` part of `Cross.toml`
+    pub fn isolate_target_dirs(&self) -> Option<bool> {
+        self.build.isolate_target_dirs
+    }
+
+    /// Returns the `build.cargo-home` part of `Cross.toml`
+    pub fn cargo_home(&self) -> Option<String> {
+        self.build.cargo_home.clone()
+    }
+
+    /// Returns the `target.{}.static` part of `Cross.toml`
+    pub fn static_linking(&self, target: &Target) -> Option<bool> {
+        self.get_target(target).and_then(|t| t.r#static)
+    }
+
+    /// Returns the `target.{}.android-api` part of `Cross.toml`
+    pub fn android_api(&self, target: &Target) -> Option<u32> {
+        self.get_target(target).and_then(|t| t.android_api)
+    }
+
+    /// Returns the `target.{}.privileged` part of `Cross.toml`
+    pub fn privileged(&self, target: &Target) -> Option<bool> {
+        self.get_target(target).and_then(|t| t.privileged)
+    }
+
     /// Returns the list of environment variables to pass through for `build`,
     pub fn env_passthrough_build(&self) -> Vec<String> {
         self.build.env.passthrough.clone()
@@ -104,6 +331,72 @@ impl CrossToml {
             .map_or(Vec::new(), |t| t.env.volumes.clone())
     }
 
+    /// Returns the `build.env-file` part of `Cross.toml`
+    pub fn env_file(&self) -> Option<String> {
+        self.build.env_file.clone()
+    }
+
+    /// Returns the `build.env-file-overrides` part of `Cross.toml`
+    pub fn env_file_overrides(&self) -> Option<bool> {
+        self.build.env_file_overrides
+    }
+
+    /// Returns the `build.network` part of `Cross.toml`
+    pub fn network(&self) -> Option<String> {
+        self.build.network.clone()
+    }
+
+    /// Returns the `build.hostname` part of `Cross.toml`
+    pub fn hostname(&self) -> Option<String> {
+        self.build.hostname.clone()
+    }
+
+    /// Returns the `build.forward-proxy` part of `Cross.toml`
+    pub fn forward_proxy(&self) -> Option<bool> {
+        self.build.forward_proxy
+    }
+
+    /// Returns the `build.incremental` part of `Cross.toml`
+    pub fn incremental(&self) -> Option<bool> {
+        self.build.incremental
+    }
+
+    /// Returns the `build.sccache` part of `Cross.toml`
+    pub fn sccache(&self) -> Option<bool> {
+        self.build.sccache
+    }
+
+    /// Returns the `build.workdir` part of `Cross.toml`
+    pub fn workdir(&self) -> Option<String> {
+        self.build.workdir.clone()
+    }
+
+    /// Returns the `build.seccomp` part of `Cross.toml`
+    pub fn seccomp(&self) -> Option<String> {
+        self.build.seccomp.clone()
+    }
+
+    /// Returns the `build.docker-args` followed by the `target.{}.docker-args` part of `Cross.toml`
+    pub fn docker_args(&self, target: &Target) -> Vec<String> {
+        let mut args = self.build.docker_args.clone();
+        if let Some(t) = self.get_target(target) {
+            args.extend(t.docker_args.iter().cloned());
+        }
+        args
+    }
+
+    /// Returns the `target.{}.runner-wrapper` part of `Cross.toml`
+    pub fn runner_wrapper(&self, target: &Target) -> Vec<String> {
+        self.get_target(target)
+            .map_or(Vec::new(), |t| t.runner_wrapper.clone())
+    }
+
+    /// Returns the `target.{}.post-build` part of `Cross.toml`
+    pub fn post_build(&self, target: &Target) -> Vec<String> {
+        self.get_target(target)
+            .map_or(Vec::new(), |t| t.post_build.clone())
+    }
+
     /// Returns the default target to build,
     pub fn default_target(&self, target_list: &TargetList) -> Option<Target> {
         self.build
@@ -112,12 +405,83 @@ impl CrossToml {
             .map(|t| Target::from(t, target_list))
     }
 
+    /// Returns the `build.targets` part of `Cross.toml`, the targets of
+    /// `--target all`
+    pub fn targets(&self, target_list: &TargetList) -> Vec<Target> {
+        self.build
+            .targets
+            .iter()
+            .map(|t| Target::from(t, target_list))
+            .collect()
+    }
+
     /// Returns a reference to the [`CrossTargetConfig`] of a specific `target`
     fn get_target(&self, target: &Target) -> Option<&CrossTargetConfig> {
         self.targets.get(target)
     }
 }
 
+/// A problem found in `Cross.toml` by `cross check-config`
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Issue {
+    /// The dotted path of the key, like `target.aarch64-unknown-linux-gnu.image`
+    pub key: String,
+    /// The line the key is on, starting at 1, when it could be found
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Issue {
+    pub fn new(toml_str: &str, key: String, message: String) -> Self {
+        Issue {
+            line: line_of(toml_str, &key),
+            key,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: `{}`: {}", self.key, self.message),
+            None => write!(f, "`{}`: {}", self.key, self.message),
+        }
+    }
+}
+
+/// The line of `key` in `toml_str`, as a key of its table or as a table
+/// header. Keys in inline tables or dotted keys aren't found.
+fn line_of(toml_str: &str, key: &str) -> Option<usize> {
+    let header = |line: &str| {
+        let line = line.trim();
+        (line.starts_with('[') && !line.starts_with("[[")).then(|| {
+            line.trim_matches(|c| c == '[' || c == ']')
+                .trim()
+                .to_string()
+        })
+    };
+    let is_key = |line: &str, name: &str| {
+        line.trim()
+            .strip_prefix(name)
+            .map_or(false, |rest| rest.trim_start().starts_with('='))
+    };
+
+    let (table, name) = key.rsplit_once('.').unwrap_or(("", key));
+    let mut current = String::new();
+    for (index, line) in toml_str.lines().enumerate() {
+        if let Some(header) = header(line) {
+            if header == key {
+                return Some(index + 1);
+            }
+            current = header;
+        } else if current == table && is_key(line, name) {
+            return Some(index + 1);
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -147,12 +511,40 @@ mod tests {
                 },
                 xargo: Some(true),
                 default_target: None,
+                targets: vec![],
+                qemu_friendly: None,
+                qemu: None,
+                memory: None,
+                cpus: None,
+                copy_artifacts: None,
+                max_image_age: None,
+                image_registry: None,
+                registry: Some(MountMode::Ro),
+                isolate_target_dirs: None,
+                cargo_home: None,
+                docker_args: vec![],
+                network: None,
+                hostname: Some("builder".to_string()),
+                forward_proxy: None,
+                incremental: Some(false),
+                sccache: None,
+                workdir: None,
+                env_file: None,
+                env_file_overrides: None,
+                seccomp: None,
+                require_target: None,
+                build_info: None,
+                cargo: None,
+                source: None,
             },
         };
 
         let test_str = r#"
           [build]
           xargo = true
+          registry = "ro"
+          incremental = false
+          hostname = "builder"
 
           [build.env]
           volumes = ["VOL1_ARG", "VOL2_ARG"]
@@ -181,6 +573,20 @@ mod tests {
                 xargo: Some(false),
                 image: Some("test-image".to_string()),
                 runner: None,
+                qemu_friendly: Some(false),
+                qemu: Some("7.2.0-1".to_string()),
+                memory: Some("4g".to_string()),
+                cpus: None,
+                r#static: Some(true),
+                android_api: None,
+                privileged: Some(false),
+                docker_args: vec!["--shm-size=1g".to_string()],
+                runner_wrapper: vec!["/setup.sh".to_string()],
+                post_build: vec!["strip {bin}".to_string()],
+                toolchain: None,
+                linker: None,
+                ar: Some("llvm-ar".to_string()),
+                bench: None,
             },
         );
 
@@ -196,6 +602,15 @@ mod tests {
             [target.aarch64-unknown-linux-gnu]
             xargo = false
             image = "test-image"
+            qemu-friendly = false
+            qemu = "7.2.0-1"
+            memory = "4g"
+            static = true
+            privileged = false
+            docker-args = ["--shm-size=1g"]
+            runner-wrapper = ["/setup.sh"]
+            post-build = ["strip {bin}"]
+            ar = "llvm-ar"
         "#;
         let (parsed_cfg, unused) = CrossToml::parse(test_str)?;
 
@@ -1,19 +1,42 @@
-use std::path::{Path, PathBuf};
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::OsStr;
+use std::hash::{Hash, Hasher};
+use std::path::{Component, Path, PathBuf};
 use std::process::{Command, ExitStatus};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{env, fs};
 
-use crate::cargo::Root;
+use crate::cargo::{Root, Subcommand};
+use crate::cross_toml::MountMode;
 use crate::errors::*;
 use crate::extensions::{CommandExt, SafeCommand};
 use crate::id;
+use crate::interpreter;
 use crate::{Config, Target};
 use atty::Stream;
 use eyre::bail;
+use once_cell::sync::OnceCell;
+use rustc_version::Version;
+use serde::Serialize;
 
 const DOCKER_IMAGES: &[&str] = &include!(concat!(env!("OUT_DIR"), "/docker-images.rs"));
 const CROSS_IMAGE: &str = "ghcr.io/cross-rs";
+const QEMU_IMAGE: &str = "docker.io/multiarch/qemu-user-static";
 const DOCKER: &str = "docker";
 const PODMAN: &str = "podman";
+/// The proxy variables forwarded into the container unless
+/// `build.forward-proxy = false`
+const PROXY_VARS: &[&str] = &[
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    "http_proxy",
+    "https_proxy",
+    "no_proxy",
+];
+
+/// The endpoint of the active docker context, see [`use_docker_context`]
+static CONTEXT_HOST: OnceCell<Option<String>> = OnceCell::new();
 
 fn get_container_engine() -> Result<std::path::PathBuf, which::Error> {
     if let Ok(ce) = env::var("CROSS_CONTAINER_ENGINE") {
@@ -23,18 +46,88 @@ fn get_container_engine() -> Result<std::path::PathBuf, which::Error> {
     }
 }
 
-pub fn docker_command(subcommand: &str) -> Result<Command> {
-    let ce = get_container_engine()
+fn container_engine() -> Result<PathBuf> {
+    get_container_engine()
         .map_err(|_| eyre::eyre!("no container engine found"))
-        .with_suggestion(|| "is docker or podman installed?")?;
-    let mut command = Command::new(ce);
+        .with_suggestion(|| "is docker or podman installed?")
+}
+
+/// The path and `--version` of the container engine, if one is installed
+pub fn engine_version() -> Option<(PathBuf, String)> {
+    let engine = get_container_engine().ok()?;
+    let version = Command::new(&engine)
+        .arg("--version")
+        .run_and_get_stdout(false)
+        .ok()?;
+    Some((engine, version.trim().to_string()))
+}
+
+/// Points the engine commands `cross` runs at the endpoint of the active
+/// docker context (like colima's or lima's socket), unless `DOCKER_HOST`
+/// already does, so they all reach the same daemon. The engine's defaults are
+/// kept if the context can't be inspected. The process environment is left
+/// alone, the endpoint is only given to the commands.
+pub fn use_docker_context(verbose: bool) {
+    if env::var_os("DOCKER_HOST").is_some() {
+        return;
+    }
+    let engine = match get_container_engine() {
+        Ok(engine) if engine.ends_with(DOCKER) => engine,
+        _ => return,
+    };
+
+    let endpoint = Command::new(engine)
+        .args([
+            "context",
+            "inspect",
+            "--format",
+            "{{.Endpoints.docker.Host}}",
+        ])
+        .run_and_get_stdout(verbose)
+        .ok()
+        .and_then(|output| context_endpoint(&output));
+    CONTEXT_HOST.set(endpoint).ok();
+}
+
+/// A command running the container `engine`, pointed at the endpoint of the
+/// docker context found by [`use_docker_context`], if any
+fn engine_command(engine: impl AsRef<OsStr>) -> Command {
+    let mut command = Command::new(engine);
+    if let Some(Some(host)) = CONTEXT_HOST.get() {
+        command.env("DOCKER_HOST", host);
+    }
+    command
+}
+
+/// The endpoint of an inspected context. Only sockets are used: TCP endpoints
+/// come with TLS settings that `DOCKER_HOST` alone doesn't carry.
+fn context_endpoint(output: &str) -> Option<String> {
+    let endpoint = output.trim();
+    endpoint
+        .starts_with("unix://")
+        .then(|| endpoint.to_string())
+}
+
+pub fn docker_command(subcommand: &str) -> Result<Command> {
+    let mut command = engine_command(container_engine()?);
     command.arg(subcommand);
-    command.args(&["--userns", "host"]);
+    command.args(["--userns", "host"]);
     Ok(command)
 }
 
-/// Register binfmt interpreters
-pub fn register(target: &Target, verbose: bool) -> Result<()> {
+/// Registers the interpreter for `target`, from the `qemu` version of
+/// `multiarch/qemu-user-static` when one is given. Its interpreters are loaded
+/// right away (`--persistent`), since the container doesn't outlive the command.
+pub fn register(target: &Target, qemu: Option<&str>, verbose: bool) -> Result<()> {
+    if let Some(version) = qemu.filter(|_| !target.is_windows()) {
+        return docker_command("run")?
+            .arg("--privileged")
+            .arg("--rm")
+            .arg(format!("{QEMU_IMAGE}:{version}"))
+            .args(["--persistent", "yes"])
+            .run(verbose);
+    }
+
     let cmd = if target.is_windows() {
         // https://www.kernel.org/doc/html/latest/admin-guide/binfmt-misc.html
         "mount binfmt_misc -t binfmt_misc /proc/sys/fs/binfmt_misc && \
@@ -48,22 +141,176 @@ pub fn register(target: &Target, verbose: bool) -> Result<()> {
         .arg("--privileged")
         .arg("--rm")
         .arg("ubuntu:16.04")
-        .args(&["sh", "-c", cmd])
+        .args(["sh", "-c", cmd])
         .run(verbose)
 }
 
-#[allow(clippy::too_many_arguments)] // TODO: refactor
-pub fn run(
+/// Removes the QEMU interpreters from binfmt_misc, for all targets
+pub fn unregister(verbose: bool) -> Result<()> {
+    docker_command("run")?
+        .arg("--privileged")
+        .arg("--rm")
+        .arg("ubuntu:16.04")
+        .args([
+            "sh",
+            "-c",
+            "mount binfmt_misc -t binfmt_misc /proc/sys/fs/binfmt_misc 2>/dev/null; \
+                for f in /proc/sys/fs/binfmt_misc/qemu-*; do \
+                    [ -f \"$f\" ] && echo -1 > \"$f\"; \
+                done; true",
+        ])
+        .run(verbose)
+}
+
+/// A host path bind-mounted into the container
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Mount {
+    pub host: PathBuf,
+    pub container: PathBuf,
+    pub read_only: bool,
+    /// Environment variable pointing at the mount inside the container
+    pub env: Option<String>,
+    /// Whether to relabel the mount for SELinux (`:Z`)
+    #[serde(skip)]
+    relabel: bool,
+}
+
+impl Mount {
+    fn new(host: impl Into<PathBuf>, container: impl Into<PathBuf>) -> Self {
+        Mount {
+            host: host.into(),
+            container: container.into(),
+            read_only: false,
+            env: None,
+            relabel: true,
+        }
+    }
+
+    fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    fn volume_arg(&self) -> String {
+        let mut options = vec![];
+        if self.relabel {
+            options.push("Z");
+        }
+        if self.read_only {
+            options.push("ro");
+        }
+
+        let mut arg = format!("{}:{}", self.host.display(), self.container.display());
+        if !options.is_empty() {
+            arg.push(':');
+            arg.push_str(&options.join(","));
+        }
+        arg
+    }
+}
+
+/// The resolved mounts of a `cross` invocation
+#[derive(Debug, Serialize)]
+pub struct Mounts {
+    /// Working directory inside the container
+    pub workdir: PathBuf,
+    /// Cargo home inside the container
+    pub cargo_home: PathBuf,
+    pub mounts: Vec<Mount>,
+    /// Where artifacts are copied out to, if the target directory isn't mounted
+    #[serde(skip)]
+    pub copy_artifacts_to: Option<PathBuf>,
+    /// The project root on the host, which persistent containers belong to
+    #[serde(skip)]
+    pub project: PathBuf,
+}
+
+impl Mounts {
+    /// The host path of `path` in the container, if it's mounted (or copied out)
+    pub fn host_path(&self, path: &Path) -> Option<PathBuf> {
+        let copied = self
+            .copy_artifacts_to
+            .as_ref()
+            .map(|target_dir| (Path::new("/target"), target_dir.as_path()));
+        self.mounts
+            .iter()
+            .map(|m| (m.container.as_path(), m.host.as_path()))
+            .chain(copied)
+            .filter(|(container, _)| path.starts_with(container))
+            .max_by_key(|(container, _)| container.components().count())
+            .map(|(container, host)| match path.strip_prefix(container) {
+                Ok(rest) if rest != Path::new("") => host.join(rest),
+                _ => host.to_path_buf(),
+            })
+    }
+
+    /// Writes the mounts as JSON to `path`, for external tooling
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).wrap_err_with(|| format!("couldn't write {}", path.display()))
+    }
+}
+
+fn validate_env_var(var: &str) -> Result<()> {
+    if var.contains('=') {
+        bail!("environment variable names must not contain the '=' character");
+    }
+
+    if var == "CROSS_RUNNER" {
+        bail!("CROSS_RUNNER environment variable name is reserved and cannot be pass through");
+    }
+
+    if let Some(index) = var.find('*') {
+        if index == 0 || index != var.len() - 1 {
+            bail!("`{var}`: only a trailing `*` after a prefix is supported in environment variable patterns");
+        }
+    }
+
+    Ok(())
+}
+
+/// Expands the `PREFIX*` patterns of `passthrough` to the names of the host
+/// variables starting with `PREFIX`, in order, without duplicates
+fn expand_passthrough(
+    passthrough: &[String],
+    host_vars: impl IntoIterator<Item = String>,
+) -> Vec<String> {
+    let mut host_vars: Vec<_> = host_vars
+        .into_iter()
+        .filter(|var| var != "CROSS_RUNNER")
+        .collect();
+    host_vars.sort();
+
+    let mut expanded: Vec<String> = vec![];
+    for var in passthrough {
+        let matches = match var.strip_suffix('*') {
+            Some(prefix) => host_vars
+                .iter()
+                .filter(|host_var| host_var.starts_with(prefix))
+                .cloned()
+                .collect(),
+            None => vec![var.clone()],
+        };
+        for var in matches {
+            if !expanded.contains(&var) {
+                expanded.push(var);
+            }
+        }
+    }
+    expanded
+}
+
+/// Resolves the host paths that are mounted into the container
+#[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
+pub fn mounts(
     target: &Target,
-    args: &[String],
     target_dir: &Option<PathBuf>,
     root: &Root,
     config: &Config,
-    uses_xargo: bool,
     sysroot: &Path,
     verbose: bool,
     docker_in_docker: bool,
-) -> Result<ExitStatus> {
+) -> Result<Mounts> {
     let mount_finder = if docker_in_docker {
         MountFinder::new(docker_read_mount_paths()?)
     } else {
@@ -72,21 +319,43 @@ pub fn run(
 
     let root = root.path();
     let home_dir = home::home_dir().ok_or_else(|| eyre::eyre!("could not find home directory"))?;
-    let cargo_dir = home::cargo_home()?;
+    let cargo_dir = home::cargo_home().ok();
+    if cargo_dir.is_none() {
+        eprintln!(
+            "Warning: couldn't determine the cargo home, so the registry and git caches \
+             aren't shared with the container"
+        );
+    }
     let xargo_dir = env::var_os("XARGO_HOME")
         .map(PathBuf::from)
         .unwrap_or_else(|| home_dir.join(".xargo"));
     let nix_store_dir = env::var_os("NIX_STORE").map(PathBuf::from);
-    let target_dir = target_dir.clone().unwrap_or_else(|| root.join("target"));
+    let target_dir = host_target_dir(
+        target_dir,
+        root,
+        target,
+        config.isolate_target_dirs()?.unwrap_or(false),
+    );
+    let copy_artifacts_to = config
+        .copy_artifacts()?
+        .unwrap_or(false)
+        .then(|| target_dir.clone());
 
     // create the directories we are going to mount before we mount them,
     // otherwise `docker` will create them but they will be owned by `root`
-    fs::create_dir(&target_dir).ok();
-    fs::create_dir(&cargo_dir).ok();
+    fs::create_dir_all(&target_dir).ok();
+    if let Some(cargo_dir) = &cargo_dir {
+        fs::create_dir(cargo_dir).ok();
+    }
     fs::create_dir(&xargo_dir).ok();
+    let cargo_home = config.cargo_home()?;
+    let registry = config.registry()?;
+    if let Some(cargo_dir) = cargo_dir.as_ref().filter(|_| registry.is_read_only()) {
+        fs::create_dir_all(cargo_dir.join(REGISTRY_DIR)).ok();
+    }
 
     // update paths to the host mounts path.
-    let cargo_dir = mount_finder.find_mount_path(cargo_dir);
+    let cargo_dir = cargo_dir.map(|dir| mount_finder.find_mount_path(dir));
     let xargo_dir = mount_finder.find_mount_path(xargo_dir);
     let target_dir = mount_finder.find_mount_path(target_dir);
     let host_root = mount_finder.find_mount_path(root);
@@ -102,38 +371,7 @@ pub fn run(
     }
     let sysroot = mount_finder.find_mount_path(sysroot);
 
-    let mut cmd = if uses_xargo {
-        SafeCommand::new("xargo")
-    } else {
-        SafeCommand::new("cargo")
-    };
-
-    cmd.args(args);
-
-    let runner = config.runner(target)?;
-
-    let mut docker = docker_command("run")?;
-
-    let validate_env_var = |var: &str| -> Result<()> {
-        if var.contains('=') {
-            bail!("environment variable names must not contain the '=' character");
-        }
-
-        if var == "CROSS_RUNNER" {
-            bail!("CROSS_RUNNER environment variable name is reserved and cannot be pass through");
-        }
-
-        Ok(())
-    };
-
-    for ref var in config.env_passthrough(target)? {
-        validate_env_var(var)?;
-
-        // Only specifying the environment variable name in the "-e"
-        // flag forwards the value from the parent shell
-        docker.args(&["-e", var]);
-    }
-    let mut env_volumes = false;
+    let mut mounts = vec![];
     for ref var in config.env_volumes(target)? {
         validate_env_var(var)?;
 
@@ -156,27 +394,287 @@ pub fn run(
                     .wrap_err_with(|| format!("when canonicalizing path `{val}`"))?;
                 mount_path = host_path.clone();
             }
-            docker.args(&[
-                "-v",
-                &format!("{}:{}", host_path.display(), mount_path.display()),
-            ]);
-            docker.args(&["-e", &format!("{}={}", var, mount_path.display())]);
-            env_volumes = true;
+            mounts.push(Mount {
+                env: Some(var.clone()),
+                relabel: false,
+                ..Mount::new(host_path, mount_path)
+            });
+        }
+    }
+
+    // the project keeps its host path when volumes may refer to it
+    let project_dir = if mounts.is_empty() {
+        PathBuf::from("/project")
+    } else {
+        mount_root
+    };
+    let cwd = env::current_dir().wrap_err("couldn't get current directory")?;
+    let workdir = container_workdir(&project_dir, root, &cwd, config.workdir().as_deref())?;
+    if let Some(cargo_dir) = &cargo_dir {
+        mounts.extend(cargo_mounts(cargo_dir, &cargo_home, registry));
+    }
+    mounts.extend(standard_mounts(
+        xargo_dir,
+        host_root,
+        &project_dir,
+        config.source()?,
+        sysroot,
+    ));
+    if copy_artifacts_to.is_none() {
+        mounts.push(Mount::new(target_dir, "/target"));
+    }
+
+    if config.sccache()? {
+        let sccache_dir = host_sccache_dir(&home_dir);
+        fs::create_dir_all(&sccache_dir).ok();
+        mounts.push(sccache_mount(mount_finder.find_mount_path(sccache_dir)));
+    }
+
+    // When running inside NixOS or using Nix packaging we need to add the Nix
+    // Store to the running container so it can load the needed binaries.
+    if let Some(nix_store) = nix_store_dir {
+        mounts.push(Mount::new(&nix_store, &nix_store));
+    }
+
+    // A Windows engine run from WSL would silently mount empty directories
+    // for paths it doesn't understand.
+    if is_wsl()
+        && container_engine()?
+            .extension()
+            .map_or(false, |ext| ext == "exe")
+    {
+        let mut untranslatable = vec![];
+        for mount in &mut mounts {
+            match wsl_windows_path(&mount.host, verbose) {
+                Some(path) => mount.host = path,
+                None => untranslatable.push(format!("`{}`", mount.host.display())),
+            }
+        }
+        if !untranslatable.is_empty() {
+            return Err(eyre::eyre!(
+                "the Windows container engine can't mount {}, which have no Windows path",
+                untranslatable.join(", ")
+            ))
+            .suggestion("move them under a Windows drive such as `/mnt/c`, or use a container engine installed in WSL");
         }
     }
 
-    docker.args(&["-e", "PKG_CONFIG_ALLOW_CROSS=1"]);
+    Ok(Mounts {
+        workdir,
+        cargo_home,
+        mounts,
+        copy_artifacts_to,
+        project: root.to_path_buf(),
+    })
+}
+
+/// The working directory in the container: the configured `workdir`, relative
+/// to the project, or else where `cross` is run from in the project, as cargo
+/// would run there
+fn container_workdir(
+    project_dir: &Path,
+    root: &Path,
+    cwd: &Path,
+    configured: Option<&str>,
+) -> Result<PathBuf> {
+    let relative = match configured {
+        Some(workdir) => {
+            let workdir = Path::new(workdir);
+            if !workdir
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(eyre::eyre!(
+                    "`workdir` must be a path in the project, got `{}`",
+                    workdir.display()
+                ))
+                .suggestion("use a path relative to the root of the project");
+            }
+            workdir
+        }
+        None => cwd.strip_prefix(root).unwrap_or_else(|_| Path::new("")),
+    };
 
-    docker.arg("--rm");
+    // the container is always Linux, whatever the host
+    let mut workdir = project_dir.display().to_string();
+    for component in relative.components() {
+        if let Component::Normal(name) = component {
+            workdir.push('/');
+            workdir.push_str(&name.to_string_lossy());
+        }
+    }
+    Ok(PathBuf::from(workdir))
+}
 
-    if target.needs_docker_privileged() {
-        docker.arg("--privileged");
+/// The host directory mounted as the container's target directory. Isolated
+/// target directories keep the artifacts (and fingerprints) of each target
+/// apart, in `target/<triple>`.
+fn host_target_dir(
+    target_dir: &Option<PathBuf>,
+    root: &Path,
+    target: &Target,
+    isolate: bool,
+) -> PathBuf {
+    let target_dir = target_dir.clone().unwrap_or_else(|| root.join("target"));
+    if isolate {
+        target_dir.join(target.triple())
+    } else {
+        target_dir
+    }
+}
+
+/// The registry cache of cargo, relative to its home
+const REGISTRY_DIR: &str = "registry";
+
+/// The cargo home is mounted read-write, so its caches are shared with the
+/// host as is; concurrent builds are safe thanks to cargo's own locking. In
+/// read-only mode, the registry is mounted over it read-only. The git caches
+/// (`git/db` and `git/checkouts`) stay writable either way, as cargo checks
+/// out git dependencies there.
+fn cargo_mounts(cargo_dir: &Path, cargo_home: &Path, mode: MountMode) -> Vec<Mount> {
+    let mut mounts = vec![Mount::new(cargo_dir, cargo_home)];
+    if mode.is_read_only() {
+        mounts.push(
+            Mount::new(cargo_dir.join(REGISTRY_DIR), cargo_home.join(REGISTRY_DIR)).read_only(),
+        );
+    }
+    mounts
+}
+
+/// The xargo home, the project (read-only with `source = "ro"`, the target
+/// directory being mounted apart) and the toolchain
+fn standard_mounts(
+    xargo_dir: PathBuf,
+    host_root: PathBuf,
+    workdir: &Path,
+    source: MountMode,
+    sysroot: PathBuf,
+) -> Vec<Mount> {
+    let mut project = Mount::new(host_root, workdir);
+    project.read_only = source.is_read_only();
+    vec![
+        Mount::new(xargo_dir, "/xargo"),
+        project,
+        Mount::new(sysroot, "/rust").read_only(),
+    ]
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    target: &Target,
+    image: &str,
+    subcommand: Option<Subcommand>,
+    args: &[String],
+    mounts: &Mounts,
+    config: &Config,
+    env_vars: &[(String, String)],
+    engine_args: &[String],
+    stdout: Option<&Path>,
+    server: bool,
+    keep_on_failure: bool,
+    uses_xargo: bool,
+    verbose: bool,
+) -> Result<ExitStatus> {
+    // `post-build` hooks find the executables in cargo's JSON messages
+    let post_build = post_build_hooks(config, target, subcommand);
+    let (args, forward_messages) = if post_build.is_empty() {
+        (args.to_vec(), false)
+    } else {
+        post_build_args(args)?
+    };
+    let args = &args[..];
+    let cmd = cargo_command(config, target, args, uses_xargo);
+
+    // `cross shell` starts the container of a build, with a shell instead of cargo
+    let shell = subcommand == Some(Subcommand::Shell);
+    if shell && !(atty::is(Stream::Stdin) && atty::is(Stream::Stdout)) {
+        bail!("`cross shell` needs an interactive terminal, but stdin or stdout isn't one");
+    }
+    let copy_artifacts = mounts.copy_artifacts_to.is_some() && !shell;
+
+    let runner = config.runner(target)?;
+
+    let mut docker = if copy_artifacts {
+        docker_command("create")?
+    } else {
+        docker_command("run")?
+    };
+
+    if server && copy_artifacts {
+        eprintln!("Warning: `--server` is ignored when copying artifacts.");
+    }
+    let server = server && !copy_artifacts && !shell;
+
+    let passthrough = config.env_passthrough(target)?;
+    for var in &passthrough {
+        validate_env_var(var)?;
+    }
+    let host_vars = env::vars_os().filter_map(|(var, _)| var.into_string().ok());
+    let passthrough = expand_passthrough(&passthrough, host_vars);
+    for var in &passthrough {
+        if var == "RUSTFLAGS" || var == "CARGO_ENCODED_RUSTFLAGS" {
+            // always forwarded, merged with the static linking flags below
+            continue;
+        }
+
+        // Only specifying the environment variable name in the "-e"
+        // flag forwards the value from the parent shell
+        docker.args(["-e", var]);
     }
 
+    if config.forward_proxy()? {
+        for var in proxy_vars(&passthrough, |var| env::var_os(var).is_some()) {
+            docker.args(["-e", var]);
+        }
+    }
+
+    docker_rustflags(
+        &mut docker,
+        config,
+        target,
+        env::var("RUSTFLAGS").ok().as_deref(),
+        env::var("CARGO_ENCODED_RUSTFLAGS").ok().as_deref(),
+    )?;
+
+    docker_env_file(&mut docker, env_vars, config.env_file_overrides()?)?;
+
+    docker.args(["-e", "PKG_CONFIG_ALLOW_CROSS=1"]);
+
+    // the container is started and removed separately when copying artifacts
+    let name = format!("cross-{target}-{}", std::process::id());
+    let keep_on_failure = keep_on_failure && !server && !shell;
+    docker_removal(&mut docker, &name, copy_artifacts || keep_on_failure);
+
+    docker_privileged(&mut docker, config, target)?;
+
+    docker_resources(&mut docker, config, target)?;
+
+    docker_android_api(&mut docker, config, target)?;
+
+    docker_seccomp(&mut docker, config, target)?;
+
+    docker_network(&mut docker, config, args);
+
+    docker_hostname(&mut docker, config)?;
+
+    docker_incremental(&mut docker, config)?;
+
+    let sccache = docker_sccache(&mut docker, config)?;
+
+    let zig_target = match config.linker(target).filter(|l| is_zig_linker(l)) {
+        Some(linker) => Some(zig_target(target, &linker)?),
+        None => None,
+    };
+    if zig_target.is_some() {
+        docker.args(["-e", &format!("{}={ZIG_CC}", target_var(target, "LINKER"))]);
+    }
+
+    docker_target_tools(&mut docker, config, target)?;
+
     // We need to specify the user for Docker, but not for Podman.
     if let Ok(ce) = get_container_engine() {
         if ce.ends_with(DOCKER) {
-            docker.args(&[
+            docker.args([
                 "--user",
                 &format!(
                     "{}:{}",
@@ -188,20 +686,20 @@ pub fn run(
     }
 
     docker
-        .args(&["-e", "XARGO_HOME=/xargo"])
-        .args(&["-e", "CARGO_HOME=/cargo"])
-        .args(&["-e", "CARGO_TARGET_DIR=/target"]);
+        .args(["-e", "XARGO_HOME=/xargo"])
+        .args(["-e", &format!("CARGO_HOME={}", mounts.cargo_home.display())])
+        .args(["-e", "CARGO_TARGET_DIR=/target"]);
 
     if let Some(username) = id::username().unwrap() {
-        docker.args(&["-e", &format!("USER={username}")]);
+        docker.args(["-e", &format!("USER={username}")]);
     }
 
     if let Ok(value) = env::var("QEMU_STRACE") {
-        docker.args(&["-e", &format!("QEMU_STRACE={value}")]);
+        docker.args(["-e", &format!("QEMU_STRACE={value}")]);
     }
 
     if let Ok(value) = env::var("CROSS_DEBUG") {
-        docker.args(&["-e", &format!("CROSS_DEBUG={value}")]);
+        docker.args(["-e", &format!("CROSS_DEBUG={value}")]);
     }
 
     if let Ok(value) = env::var("DOCKER_OPTS") {
@@ -209,255 +707,2762 @@ pub fn run(
         docker.args(&opts);
     }
 
-    docker
-        .args(&[
-            "-e",
-            &format!("CROSS_RUNNER={}", runner.unwrap_or_default()),
-        ])
-        .args(&["-v", &format!("{}:/xargo:Z", xargo_dir.display())])
-        .args(&["-v", &format!("{}:/cargo:Z", cargo_dir.display())])
-        // Prevent `bin` from being mounted inside the Docker container.
-        .args(&["-v", "/cargo/bin"]);
-    if env_volumes {
-        docker.args(&[
-            "-v",
-            &format!("{}:{}:Z", host_root.display(), mount_root.display()),
-        ]);
-    } else {
-        docker.args(&["-v", &format!("{}:/project:Z", host_root.display())]);
+    docker.args([
+        "-e",
+        &format!("CROSS_RUNNER={}", runner.unwrap_or_default()),
+    ]);
+
+    docker_mounts(&mut docker, mounts);
+
+    let interactive = atty::is(Stream::Stdin);
+    let mut tty = vec![];
+    if interactive {
+        tty.push("-i");
+        if atty::is(Stream::Stdout) && atty::is(Stream::Stderr) {
+            tty.push("-t");
+        }
+    }
+    // the persistent container is attached to by each build instead
+    if !server {
+        docker.args(&tty);
     }
-    docker
-        .args(&["-v", &format!("{}:/rust:Z,ro", sysroot.display())])
-        .args(&["-v", &format!("{}:/target:Z", target_dir.display())]);
 
-    if env_volumes {
-        docker.args(&["-w", &mount_root.display().to_string()]);
-    } else {
-        docker.args(&["-w", "/project"]);
+    if let Some(max_age) = config.max_image_age()? {
+        refresh_image(image, max_age, verbose)?;
     }
 
-    // When running inside NixOS or using Nix packaging we need to add the Nix
-    // Store to the running container so it can load the needed binaries.
-    if let Some(nix_store) = nix_store_dir {
-        docker.args(&[
-            "-v",
-            &format!("{}:{}:Z", nix_store.display(), nix_store.display()),
-        ]);
+    docker_extra_args(
+        &mut docker,
+        config,
+        target,
+        user_docker_args_path().as_deref(),
+        engine_args,
+    )?;
+
+    let mut script = format!("PATH=$PATH:/rust/bin {:?}", cmd);
+    if let Some(stdout) = stdout {
+        script = format!("{script} > {}", stdout.display());
+    }
+    if !post_build.is_empty() {
+        script = post_build_script(&script, &post_build, forward_messages);
+    }
+    if let Some(zig_target) = &zig_target {
+        script = format!("{} && {script}", zig_cc_setup(zig_target));
+    }
+    if subcommand == Some(Subcommand::Deb) {
+        script = format!("{CARGO_DEB_CHECK} && {script}");
+    }
+    if sccache {
+        script = format!("{SCCACHE_CHECK} && {script}");
     }
 
-    if atty::is(Stream::Stdin) {
-        docker.arg("-i");
-        if atty::is(Stream::Stdout) && atty::is(Stream::Stderr) {
-            docker.arg("-t");
+    if shell {
+        shell_command(&mut docker, image);
+        return docker.run_and_get_status(verbose);
+    }
+
+    if server {
+        let server = Server::new(target, &mounts.project, &docker, image);
+        server.start(docker, image, config.server_timeout()?, verbose)?;
+        return server.exec(&tty, &script, verbose);
+    }
+
+    docker.arg(image).args(["sh", "-c", &script]);
+
+    match mounts.copy_artifacts_to.as_ref().filter(|_| copy_artifacts) {
+        Some(target_dir) => {
+            docker.run_and_get_stdout(verbose)?;
+            let [mut cp_in, mut start, mut cp, mut rm] =
+                copy_artifacts_commands(&container_engine()?, &name, target_dir, interactive);
+            if let Err(err) = cp_in.run(verbose) {
+                rm.run(verbose)?;
+                return Err(err);
+            }
+            let status = start.run_and_get_status(verbose);
+            // artifacts are copied out and the container removed regardless
+            // of whether the build succeeded, unless it's kept for debugging
+            let copied = cp.run(verbose);
+            if !keeps_container(keep_on_failure, &status) {
+                rm.run(verbose)?;
+            }
+            copied?;
+            status
+        }
+        None => {
+            let status = docker.run_and_get_status(verbose);
+            if keep_on_failure && !keeps_container(keep_on_failure, &status) {
+                engine_command(container_engine()?)
+                    .args(["rm", &name])
+                    .run(verbose)?;
+            }
+            status
         }
     }
+    .map(|status| {
+        if keeps_container(keep_on_failure, &Ok(status)) {
+            let engine = container_engine().map_or(DOCKER.into(), |e| e.display().to_string());
+            eprintln!(
+                "The container `{name}` of the failed build was kept. It has stopped, so get \
+                 into a copy of it with:\n  \
+                 {engine} commit {name} {name} && {engine} run -it --rm --entrypoint bash {name}\n\
+                 and remove both with `{engine} rm {name} && {engine} rmi {name}`."
+            );
+        }
+        status
+    })
+}
 
-    docker
-        .arg(&image(config, target)?)
-        .args(&["sh", "-c", &format!("PATH=$PATH:/rust/bin {:?}", cmd)])
-        .run_and_get_status(verbose)
+/// The `post-build` hooks of `target`, which only follow `cross build`
+fn post_build_hooks(
+    config: &Config,
+    target: &Target,
+    subcommand: Option<Subcommand>,
+) -> Vec<String> {
+    match subcommand {
+        Some(Subcommand::Build) => config.post_build(target),
+        _ => Vec::new(),
+    }
 }
 
-pub fn image(config: &Config, target: &Target) -> Result<String> {
-    if let Some(image) = config.image(target)? {
-        return Ok(image);
+/// The arguments of a build with `post-build` hooks, which read cargo's JSON
+/// messages, and whether the messages were asked for and are printed as well
+fn post_build_args(args: &[String]) -> Result<(Vec<String>, bool)> {
+    let end = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    let mut formats = vec![];
+    let mut iter = args[..end].iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--message-format" {
+            formats.extend(iter.next().map(String::as_str));
+        } else if let Some(format) = arg.strip_prefix("--message-format=") {
+            formats.push(format);
+        }
     }
 
-    if !DOCKER_IMAGES.contains(&target.triple()) {
-        bail!(
-            "`cross` does not provide a Docker image for target {target}, \
-               specify a custom image in `Cross.toml`."
-        );
+    if let Some(format) = formats.iter().find(|f| !f.starts_with("json")) {
+        bail!("`post-build` hooks read cargo's JSON messages, which `--message-format {format}` doesn't print");
     }
+    let mut args = args.to_vec();
+    if formats.is_empty() {
+        args.insert(end, "--message-format=json-render-diagnostics".to_string());
+    }
+    Ok((args, !formats.is_empty()))
+}
 
-    let version = if include_str!(concat!(env!("OUT_DIR"), "/commit-info.txt")).is_empty() {
-        env!("CARGO_PKG_VERSION")
+/// Runs the `post-build` hooks once `build` succeeds. A hook with `{bin}` runs
+/// for each executable listed in cargo's messages, and the others run once.
+fn post_build_script(build: &str, hooks: &[String], forward_messages: bool) -> String {
+    let mut script = if forward_messages {
+        format!("({build} > {POST_BUILD_MESSAGES}; code=$?; cat {POST_BUILD_MESSAGES}; exit $code)")
     } else {
-        "main"
+        format!("{build} > {POST_BUILD_MESSAGES}")
     };
+    for hook in hooks {
+        let hook = if hook.contains("{bin}") {
+            format!(
+                "grep -o '\"executable\":\"[^\"]*\"' {POST_BUILD_MESSAGES} | cut -d '\"' -f 4 | \
+                 while IFS= read -r bin; do {} || exit $?; done",
+                hook.replace("{bin}", "\"$bin\"")
+            )
+        } else {
+            hook.clone()
+        };
+        script = format!("{script} && {hook}");
+    }
+    script
+}
 
-    Ok(format!("{CROSS_IMAGE}/{target}:{version}"))
+/// Runs a shell in `image` in place of its entrypoint, which can get in the
+/// way, with the toolchain in the `PATH` as in a build
+fn shell_command(docker: &mut Command, image: &str) {
+    docker
+        .args(["--entrypoint", "/bin/sh"])
+        .arg(image)
+        .args(["-c", "export PATH=$PATH:/rust/bin && exec /bin/sh -i"]);
 }
 
-#[cfg(target_os = "windows")]
-fn wslpath(path: &Path, verbose: bool) -> Result<PathBuf> {
-    let wslpath = which::which("wsl.exe")
-        .map_err(|_| eyre::eyre!("could not find wsl.exe"))
-        .warning("usage of `env.volumes` requires WSL on Windows")
-        .suggestion("is WSL installed on the host?")?;
+/// Containers are removed as they exit with `--rm`, unless they're removed
+/// separately, by name: when artifacts are copied out of them, or to keep
+/// the container of a failed build with `--keep-container-on-failure`
+fn docker_removal(docker: &mut Command, name: &str, removed_separately: bool) {
+    if removed_separately {
+        docker.args(["--name", name]);
+    } else {
+        docker.arg("--rm");
+    }
+}
 
-    Command::new(wslpath)
-        .arg("-e")
-        .arg("wslpath")
-        .arg("-a")
-        .arg(path)
-        .run_and_get_stdout(verbose)
-        .wrap_err_with(|| {
-            format!(
-                "could not get linux compatible path for `{}`",
-                path.display()
-            )
-        })
-        .map(|s| s.trim().into())
+/// Whether the container of a build is kept once it exits
+fn keeps_container(keep_on_failure: bool, status: &Result<ExitStatus>) -> bool {
+    keep_on_failure && !status.as_ref().map_or(false, ExitStatus::success)
 }
 
-fn docker_read_mount_paths() -> Result<Vec<MountDetail>> {
-    let hostname = env::var("HOSTNAME").wrap_err("HOSTNAME environment variable not found")?;
+/// Where the persistent container keeps track of the builds attached to it
+const SERVER_DIR: &str = "/tmp/cross-server";
+
+/// A long-lived container for a project and target, which builds are run in
+/// with `exec`, keeping its filesystem caches warm between builds.
+struct Server {
+    name: String,
+    project: String,
+    /// Identifies the mounts, environment and image the container was started
+    /// with, so a container started with others is replaced
+    key: String,
+}
 
-    let docker_path = which::which(DOCKER)?;
-    let mut docker: Command = {
-        let mut command = Command::new(docker_path);
-        command.arg("inspect");
-        command.arg(hostname);
-        command
+impl Server {
+    fn new(target: &Target, project: &Path, docker: &Command, image: &str) -> Self {
+        let project = format!("{:016x}", hash(project));
+        Server {
+            name: format!("cross-server-{target}-{project}"),
+            project,
+            key: format!("{:016x}", server_key(docker, image)),
+        }
+    }
+
+    /// Starts the container, unless it's running with the same configuration
+    fn start(
+        &self,
+        mut docker: Command,
+        image: &str,
+        timeout: Duration,
+        verbose: bool,
+    ) -> Result<()> {
+        let engine = container_engine()?;
+        let inspect = engine_command(&engine)
+            .args(["inspect", "--format"])
+            .arg("{{.State.Running}} {{index .Config.Labels \"cross.server\"}}")
+            .arg(&self.name)
+            .run_and_get_stdout(verbose);
+        match inspect.as_deref().map(str::trim) {
+            Ok(state) if state == format!("true {}", self.key) => return Ok(()),
+            Ok(_) => {
+                eprintln!(
+                    "Note: restarting `{}`, started with other mounts or environment.",
+                    self.name
+                );
+                engine_command(&engine)
+                    .args(["rm", "--force", &self.name])
+                    .run_and_get_stdout(verbose)?;
+            }
+            // there's no such container
+            Err(_) => {}
+        }
+
+        docker
+            .args(["--detach", "--name", &self.name])
+            .args(["--label", &format!("cross.server={}", self.key)])
+            .args(["--label", &format!("cross.server.project={}", self.project)])
+            .arg(image)
+            .args(["sh", "-c", &server_script(timeout)])
+            .run_and_get_stdout(verbose)
+            .map(drop)
+    }
+
+    /// Runs `script` in the container, tracked so the container isn't stopped
+    /// while it runs
+    fn exec(&self, tty: &[&str], script: &str, verbose: bool) -> Result<ExitStatus> {
+        let script = format!(
+            "touch {SERVER_DIR}/builds/$$; ({script}); status=$?; \
+             rm -f {SERVER_DIR}/builds/$$; touch {SERVER_DIR}/alive; exit $status"
+        );
+        engine_command(container_engine()?)
+            .arg("exec")
+            .args(tty)
+            .arg(&self.name)
+            .args(["sh", "-c", &script])
+            .run_and_get_status(verbose)
+    }
+}
+
+/// Keeps the container alive until no build has been attached to it for `timeout`
+fn server_script(timeout: Duration) -> String {
+    format!(
+        "mkdir -p {SERVER_DIR}/builds && touch {SERVER_DIR}/alive && while sleep 10; do \
+           if [ -n \"$(ls -A {SERVER_DIR}/builds)\" ]; then touch {SERVER_DIR}/alive; \
+           elif [ $(($(date +%s) - $(stat -c %Y {SERVER_DIR}/alive))) -ge {} ]; then exit 0; fi; \
+         done",
+        timeout.as_secs()
+    )
+}
+
+/// Hashes the arguments of the `run` command, and the values of the variables
+/// it forwards by name
+fn server_key(docker: &Command, image: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let mut args = docker.get_args();
+    while let Some(arg) = args.next() {
+        arg.hash(&mut hasher);
+        if arg == "-e" {
+            if let Some(var) = args.next() {
+                var.hash(&mut hasher);
+                let set = docker.get_envs().find(|(k, _)| *k == var).map(|(_, v)| v);
+                match set {
+                    Some(value) => value.map(ToOwned::to_owned),
+                    None => env::var_os(var),
+                }
+                .hash(&mut hasher);
+            }
+        }
+    }
+    image.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Removes the persistent containers of the project at `root`, if any
+pub fn remove_servers(root: &Path, verbose: bool) -> Result<()> {
+    let engine = match get_container_engine() {
+        Ok(engine) => engine,
+        Err(_) => return Ok(()),
+    };
+    // without an engine to ask, there's nothing to remove
+    let containers = match engine_command(&engine)
+        .args(["ps", "--all", "--quiet", "--filter"])
+        .arg(format!("label=cross.server.project={:016x}", hash(root)))
+        .run_and_get_stdout(verbose)
+    {
+        Ok(containers) => containers,
+        Err(_) => return Ok(()),
     };
+    let containers: Vec<_> = containers.split_whitespace().collect();
+    if !containers.is_empty() {
+        engine_command(&engine)
+            .args(["rm", "--force"])
+            .args(&containers)
+            .run_and_get_stdout(verbose)?;
+    }
 
-    let output = docker.run_and_get_stdout(false)?;
-    let info = serde_json::from_str(&output).wrap_err("failed to parse docker inspect output")?;
-    dockerinfo_parse_mounts(&info)
+    Ok(())
+}
+
+/// The commands following `create` for container `name`: the target directory
+/// is copied in, the build started, and the artifacts copied out before removal.
+///
+/// Copying in (in archive mode, keeping ownership) creates `/target` owned by
+/// the container user, and keeps previous artifacts around for incremental builds.
+fn copy_artifacts_commands(
+    engine: &Path,
+    name: &str,
+    target_dir: &Path,
+    interactive: bool,
+) -> [Command; 4] {
+    let mut cp_in = engine_command(engine);
+    cp_in
+        .args(["cp", "--archive"])
+        .arg(target_dir.join("."))
+        .arg(format!("{name}:/target"));
+
+    let mut start = engine_command(engine);
+    start.args(["start", "--attach"]);
+    if interactive {
+        start.arg("--interactive");
+    }
+    start.arg(name);
+
+    let mut cp = engine_command(engine);
+    cp.arg("cp")
+        .arg(format!("{name}:/target/."))
+        .arg(target_dir);
+
+    let mut rm = engine_command(engine);
+    rm.args(["rm", name]);
+
+    [cp_in, start, cp, rm]
+}
+
+fn docker_mounts(docker: &mut Command, mounts: &Mounts) {
+    for mount in &mounts.mounts {
+        docker.args(["-v", &mount.volume_arg()]);
+        if let Some(var) = &mount.env {
+            docker.args(["-e", &format!("{}={}", var, mount.container.display())]);
+        }
+    }
+
+    // Prevent `bin` from being mounted inside the Docker container.
+    docker.args(["-v", &mounts.cargo_home.join("bin").display().to_string()]);
+    docker.args(["-w", &mounts.workdir.display().to_string()]);
+}
+
+/// Arguments that aren't modelled by `cross`, which come after its own so they
+/// can override them: `build.docker-args`, then `target.TARGET.docker-args`,
+/// then those in the per-user file, and finally `--engine-arg`.
+fn docker_extra_args(
+    docker: &mut Command,
+    config: &Config,
+    target: &Target,
+    user_args: Option<&Path>,
+    engine_args: &[String],
+) -> Result<()> {
+    docker.args(config.docker_args(target));
+    if let Some(path) = user_args {
+        docker_user_args(docker, path)?;
+    }
+    docker.args(engine_args);
+
+    Ok(())
+}
+
+/// The per-user file of extra `docker run` arguments, for machine-specific
+/// flags that don't belong in the project's `Cross.toml`
+fn user_docker_args_path() -> Option<PathBuf> {
+    home::home_dir().map(|home| home.join(".config").join("cross").join("docker-args"))
+}
+
+/// Appends the arguments in `path`, split on whitespace, skipping empty lines
+/// and `#` comments. A missing file is not an error.
+fn docker_user_args(docker: &mut Command, path: &Path) -> Result<()> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).wrap_err_with(|| format!("couldn't read {}", path.display())),
+    };
+
+    for line in contents.lines().map(str::trim) {
+        if !line.is_empty() && !line.starts_with('#') {
+            docker.args(line.split_whitespace());
+        }
+    }
+
+    Ok(())
+}
+
+/// Passes the variables of the env file by name, their values only being set
+/// for the engine process so they don't show up in its arguments. Variables
+/// set on the host win, unless `overrides`.
+fn docker_env_file(docker: &mut Command, vars: &[(String, String)], overrides: bool) -> Result<()> {
+    for (var, value) in vars {
+        validate_env_var(var)?;
+
+        if overrides || env::var_os(var).is_none() {
+            docker.env(var, value);
+        }
+        docker.args(["-e", var]);
+    }
+
+    Ok(())
+}
+
+/// `cargo deb` needs `cargo-deb` in the image, since the cargo home's `bin`
+/// isn't mounted
+const CARGO_DEB_CHECK: &str = "command -v cargo-deb > /dev/null || { \
+    echo 'error: `cargo-deb` is not installed in the image, use a custom image that has it' >&2; \
+    exit 127; }";
+
+/// Where the messages of a build with `post-build` hooks are written, in the
+/// container rather than the target directory
+const POST_BUILD_MESSAGES: &str = "/tmp/cross-post-build.json";
+
+const SCCACHE_CHECK: &str = "command -v sccache > /dev/null || { \
+    echo 'error: `build.sccache` requires `sccache` in the image, use a custom image that has it' >&2; \
+    exit 127; }";
+
+/// Where the cache of `sccache` is mounted in the container
+const SCCACHE_DIR: &str = "/sccache";
+
+/// The cache of `sccache` on the host: `SCCACHE_DIR`, or else its default
+fn host_sccache_dir(home_dir: &Path) -> PathBuf {
+    env::var_os("SCCACHE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            if cfg!(target_os = "macos") {
+                home_dir.join("Library/Caches/Mozilla.sccache")
+            } else {
+                home_dir.join(".cache/sccache")
+            }
+        })
+}
+
+fn sccache_mount(host_dir: PathBuf) -> Mount {
+    Mount {
+        env: Some("SCCACHE_DIR".to_string()),
+        ..Mount::new(host_dir, SCCACHE_DIR)
+    }
+}
+
+/// Wraps `rustc` with `sccache` when `build.sccache` is set, returning whether
+/// it is
+fn docker_sccache(docker: &mut Command, config: &Config) -> Result<bool> {
+    let sccache = config.sccache()?;
+    if sccache {
+        docker.args(["-e", "RUSTC_WRAPPER=sccache"]);
+    }
+
+    Ok(sccache)
+}
+
+/// The `zig cc` wrapper used as the linker, written when the container starts
+const ZIG_CC: &str = "/tmp/cross-zig-cc";
+
+/// The runners the images know, passed to them as `CROSS_RUNNER`. Other
+/// runners are commands, that cargo runs the binaries with.
+const IMAGE_RUNNERS: &[&str] = &["native", "qemu-user", "qemu-system", "qemu", "none"];
+
+/// The variable cargo reads the `name` setting of `target` from, like
+/// `CARGO_TARGET_ARMV7_UNKNOWN_LINUX_GNUEABIHF_LINKER` for `LINKER`
+fn target_var(target: &Target, name: &str) -> String {
+    format!(
+        "CARGO_TARGET_{}_{name}",
+        target
+            .triple()
+            .to_ascii_uppercase()
+            .replace(['-', '.'], "_")
+    )
+}
+
+fn is_zig_linker(linker: &str) -> bool {
+    linker == "zig" || linker.starts_with("zig.")
+}
+
+/// Points cargo at the `linker` (other than `zig`), `ar` and `runner` (other
+/// than the ones of the images) configured for `target`
+fn docker_target_tools(docker: &mut Command, config: &Config, target: &Target) -> Result<()> {
+    let linker = config.linker(target).filter(|l| !is_zig_linker(l));
+    let runner = config
+        .runner(target)?
+        .filter(|r| !IMAGE_RUNNERS.contains(&r.as_str()));
+    for (name, value) in [
+        ("LINKER", linker),
+        ("AR", config.ar(target)),
+        ("RUNNER", runner),
+    ] {
+        if let Some(value) = value {
+            docker.args(["-e", &format!("{}={value}", target_var(target, name))]);
+        }
+    }
+
+    Ok(())
+}
+
+/// The zig target for `target`, given `linker = "zig"`, or `"zig.<version>"`
+/// to link against that version of glibc
+fn zig_target(target: &Target, linker: &str) -> Result<String> {
+    let glibc = match linker.strip_prefix("zig") {
+        Some("") => None,
+        Some(version) if version.starts_with('.') => Some(version),
+        _ => bail!(
+            "unsupported linker `{linker}` for {target}, expected `zig` or `zig.<glibc version>`"
+        ),
+    };
+
+    let mut parts = target.triple().split('-');
+    let arch = parts.next().unwrap_or_default();
+    let (os, env) = match (parts.nth(1), parts.next()) {
+        (Some("linux"), Some(env)) => ("linux", env),
+        _ => bail!("`linker = \"zig\"` is only supported for Linux targets, not {target}"),
+    };
+    let arch = match arch {
+        "i586" | "i686" => "x86",
+        "riscv64gc" => "riscv64",
+        arch if arch.starts_with("armv") || arch == "arm" => "arm",
+        arch if arch.starts_with("thumbv") => "thumb",
+        arch => arch,
+    };
+
+    let mut zig_target = format!("{arch}-{os}-{env}");
+    if let Some(glibc) = glibc {
+        if !env.starts_with("gnu") {
+            bail!("a glibc version is given in `linker = \"{linker}\"`, but {target} doesn't use glibc");
+        }
+        zig_target.push_str(glibc);
+    }
+    Ok(zig_target)
+}
+
+/// Shell commands writing the `zig cc` wrapper, or failing if the image lacks `zig`
+fn zig_cc_setup(zig_target: &str) -> String {
+    format!(
+        "{{ command -v zig >/dev/null || {{ echo 'error: `linker = \"zig\"` requires `zig` in the image' >&2; exit 1; }}; }} && \
+         printf '#!/bin/sh\\nexec zig cc -target {zig_target} \"$@\"\\n' > {ZIG_CC} && chmod +x {ZIG_CC}"
+    )
+}
+
+fn docker_network(docker: &mut Command, config: &Config, args: &[String]) {
+    if let Some(network) = config.network() {
+        if network == "none" && !args.iter().any(|a| a == "--offline" || a == "--frozen") {
+            eprintln!(
+                "Warning: the container has no network, so cargo can't download \
+                 dependencies. Pass `--offline` to only use those already in the cargo home."
+            );
+        }
+        docker.args(["--network", &network]);
+    }
+}
+
+fn docker_hostname(docker: &mut Command, config: &Config) -> Result<()> {
+    if let Some(hostname) = config.hostname()? {
+        // the container shares the host's name on its network
+        if config.network().as_deref() == Some("host") {
+            eyre::bail!("`hostname` can't be set when the container uses the host's network");
+        }
+        docker.args(["--hostname", &hostname]);
+    }
+
+    Ok(())
+}
+
+fn docker_incremental(docker: &mut Command, config: &Config) -> Result<()> {
+    if let Some(incremental) = config.incremental()? {
+        let value = if incremental { "1" } else { "0" };
+        docker.args(["-e", &format!("CARGO_INCREMENTAL={value}")]);
+    }
+
+    Ok(())
+}
+
+/// `target.{}.privileged` overrides the built-in check, which only enables
+/// privileged mode for 32-bit Android targets.
+fn docker_privileged(docker: &mut Command, config: &Config, target: &Target) -> Result<()> {
+    let privileged = config
+        .privileged(target)?
+        .unwrap_or_else(|| target.needs_docker_privileged());
+    if privileged {
+        docker.arg("--privileged");
+    }
+
+    Ok(())
+}
+
+/// The command run in the container: `xargo`, or the configured `cargo`,
+/// behind the `runner-wrapper` of the target and a `--` when there's one
+fn cargo_command(
+    config: &Config,
+    target: &Target,
+    args: &[String],
+    uses_xargo: bool,
+) -> SafeCommand {
+    let cargo = if uses_xargo {
+        "xargo".to_string()
+    } else {
+        config.cargo()
+    };
+    let wrapper = config.runner_wrapper(target);
+    let mut cmd = match wrapper.split_first() {
+        Some((program, wrapper_args)) => {
+            let mut cmd = SafeCommand::new(program);
+            cmd.args(wrapper_args).arg(&"--").arg(&cargo);
+            cmd
+        }
+        None => SafeCommand::new(cargo),
+    };
+    cmd.args(args);
+    cmd
+}
+
+/// The proxy variables set on the host, in either case, that aren't already
+/// in `passthrough`
+fn proxy_vars(passthrough: &[String], is_set: impl Fn(&str) -> bool) -> Vec<&'static str> {
+    PROXY_VARS
+        .iter()
+        .copied()
+        .filter(|var| is_set(var) && !passthrough.iter().any(|p| p == var))
+        .collect()
+}
+
+fn docker_resources(docker: &mut Command, config: &Config, target: &Target) -> Result<()> {
+    if let Some(memory) = config.memory(target)? {
+        docker.args(["--memory", &memory]);
+    }
+    if let Some(cpus) = config.cpus(target)? {
+        docker.args(["--cpus", &cpus]);
+    }
+
+    Ok(())
+}
+
+fn docker_android_api(docker: &mut Command, config: &Config, target: &Target) -> Result<()> {
+    if let Some(api) = config.android_api(target)? {
+        docker.args(["-e", &format!("ANDROID_API={api}")]);
+    }
+
+    Ok(())
+}
+
+/// Forwards the rustflags of the host, with `+crt-static` appended when
+/// `static = true`. Cargo only reads the first source of rustflags it finds,
+/// so the variables are left unset otherwise, not to hide the ones of
+/// `.cargo/config.toml`; musl targets link statically by default anyway, so
+/// nothing is appended for them.
+fn docker_rustflags(
+    docker: &mut Command,
+    config: &Config,
+    target: &Target,
+    rustflags: Option<&str>,
+    encoded: Option<&str>,
+) -> Result<()> {
+    let static_linking = config.static_linking(target)? == Some(true) && !target.is_musl();
+    let (var, value) = match (encoded, rustflags) {
+        (Some(encoded), _) if static_linking => {
+            ("CARGO_ENCODED_RUSTFLAGS", static_encoded_rustflags(encoded))
+        }
+        (Some(encoded), _) => ("CARGO_ENCODED_RUSTFLAGS", encoded.to_string()),
+        (None, rustflags) if static_linking => ("RUSTFLAGS", static_rustflags(rustflags)),
+        (None, Some(rustflags)) => ("RUSTFLAGS", rustflags.to_string()),
+        (None, None) => return Ok(()),
+    };
+
+    docker.env(var, value);
+    docker.args(["-e", var]);
+
+    Ok(())
+}
+
+const CRT_STATIC: &str = "target-feature=+crt-static";
+
+fn has_crt_static<'a>(mut flags: impl Iterator<Item = &'a str>) -> bool {
+    flags.any(|flag| flag.trim_start_matches("-C") == CRT_STATIC)
+}
+
+/// Appends `-C target-feature=+crt-static` to `rustflags`, unless present
+fn static_rustflags(rustflags: Option<&str>) -> String {
+    let rustflags = rustflags.unwrap_or_default().trim();
+    if has_crt_static(rustflags.split_whitespace()) {
+        rustflags.to_string()
+    } else if rustflags.is_empty() {
+        format!("-C {CRT_STATIC}")
+    } else {
+        format!("{rustflags} -C {CRT_STATIC}")
+    }
+}
+
+/// Appends `-C target-feature=+crt-static` to the `0x1f`-separated flags of
+/// `CARGO_ENCODED_RUSTFLAGS`, unless present
+fn static_encoded_rustflags(encoded: &str) -> String {
+    if has_crt_static(encoded.split('\x1f')) {
+        encoded.to_string()
+    } else if encoded.is_empty() {
+        format!("-C\x1f{CRT_STATIC}")
+    } else {
+        format!("{encoded}\x1f-C\x1f{CRT_STATIC}")
+    }
+}
+
+/// QEMU user-mode emulation trips over syscalls that Docker's default seccomp
+/// profile rejects, so targets run under an interpreter, as decided by `runner`
+/// or the triple, are unconfined unless opted out.
+///
+/// A configured `seccomp` profile is used for any target instead.
+fn docker_seccomp(docker: &mut Command, config: &Config, target: &Target) -> Result<()> {
+    if let Some(seccomp) = config.seccomp() {
+        if seccomp == "unconfined" {
+            eprintln!(
+                "Warning: the container runs without a seccomp profile, as `seccomp = \"unconfined\"`."
+            );
+        }
+        docker.args(["--security-opt", &format!("seccomp={seccomp}")]);
+        return Ok(());
+    }
+
+    let emulated = interpreter::is_needed(target, config.runner(target)?.as_deref());
+    if emulated && config.qemu_friendly(target)?.unwrap_or(true) {
+        docker.args(["--security-opt", "seccomp=unconfined"]);
+    }
+
+    Ok(())
+}
+
+/// Pulls `image` again if the cached copy was pulled more than `max_age` ago.
+/// Pulls are recorded in `~/.cache/cross/image-pulls`, images pulled some other
+/// way are aged from when they were created.
+fn refresh_image(image: &str, max_age: Duration, verbose: bool) -> Result<()> {
+    let created = engine_command(container_engine()?)
+        .args(["image", "inspect", "--format", "{{.Created}}", image])
+        .run_and_get_stdout(verbose);
+    // images that aren't cached yet are pulled by `run` anyway
+    let created = match created.ok().and_then(|c| parse_timestamp(&c)) {
+        Some(created) => created,
+        None => return Ok(()),
+    };
+
+    let (cache, cached) = read_cache("image-pulls");
+    let pulled = cache_entry(&cached, image)
+        .and_then(|secs| secs.parse().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    let now = SystemTime::now();
+    if is_stale(created, pulled, now, max_age) {
+        let pulled = engine_command(container_engine()?)
+            .args(["pull", image])
+            .run(verbose);
+        match pulled {
+            Ok(()) => {
+                let secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                write_cache(cache, &with_cache_entry(&cached, image, &secs.to_string()));
+            }
+            Err(err) => eprintln!(
+                "Warning: couldn't refresh stale image `{image}`, using the cached one: {err}"
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// The `key value` lines cached in `~/.cache/cross/{name}`, and where they are
+fn read_cache(name: &str) -> (Option<PathBuf>, String) {
+    let path = home::home_dir().map(|home| home.join(".cache/cross").join(name));
+    let cached = path
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    (path, cached)
+}
+
+fn write_cache(path: Option<PathBuf>, cache: &str) {
+    if let Some(path) = path {
+        fs::create_dir_all(path.parent().unwrap()).ok();
+        fs::write(&path, cache).ok();
+    }
+}
+
+fn cache_entry<'a>(cache: &'a str, key: &str) -> Option<&'a str> {
+    cache
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(cached, _)| *cached == key)
+        .map(|(_, value)| value)
+}
+
+/// `cache` with only the entries whose key is kept by `keep`
+fn retain_cache_entries(cache: &str, keep: impl Fn(&str) -> bool) -> String {
+    cache
+        .lines()
+        .filter(|line| line.split_once(' ').map_or(false, |(key, _)| keep(key)))
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+/// `cache` with the entry for `key` replaced by `value`
+fn with_cache_entry(cache: &str, key: &str, value: &str) -> String {
+    cache
+        .lines()
+        .filter(|line| {
+            line.split_once(' ')
+                .map_or(true, |(cached, _)| cached != key)
+        })
+        .chain(std::iter::once(format!("{key} {value}").as_str()))
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+/// The digest the cached `image` was pulled with. Images that aren't cached,
+/// or that were built locally, have none.
+pub fn image_digest(image: &str, verbose: bool) -> Result<Option<String>> {
+    let digests = engine_command(container_engine()?)
+        .args([
+            "image",
+            "inspect",
+            "--format",
+            "{{json .RepoDigests}}",
+            image,
+        ])
+        .run_and_get_stdout(verbose);
+    Ok(digests.ok().and_then(|d| parse_repo_digest(&d)))
 }
 
-fn dockerinfo_parse_mounts(info: &serde_json::Value) -> Result<Vec<MountDetail>> {
-    let mut mounts = dockerinfo_parse_user_mounts(info);
-    let root_info = dockerinfo_parse_root_mount_path(info)?;
-    mounts.push(root_info);
-    Ok(mounts)
-}
+/// The digest `image` resolves to in its registry now, from `buildx
+/// imagetools`, unless the registry can't be reached or the engine has no
+/// `buildx`
+pub fn remote_digest(image: &str, verbose: bool) -> Result<Option<String>> {
+    let output = engine_command(container_engine()?)
+        .args(["buildx", "imagetools", "inspect", image])
+        .run_and_get_stdout(verbose);
+    Ok(output.ok().and_then(|o| parse_imagetools_digest(&o)))
+}
+
+fn parse_imagetools_digest(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("Digest:"))
+        .map(|digest| digest.trim().to_string())
+}
+
+fn parse_repo_digest(digests: &str) -> Option<String> {
+    let digests: Vec<String> = serde_json::from_str(digests.trim()).ok()?;
+    digests
+        .first()?
+        .split_once('@')
+        .map(|(_, digest)| digest.to_string())
+}
+
+/// The version of the `rustc` the builds in `image` run: the image's own if it
+/// has one on its `PATH`, and otherwise the toolchain mounted at `/rust`.
+/// Finding out runs a container, so it's cached by image ID, along with the
+/// `toolchain` version it was found with, in `~/.cache/cross/image-rustc-versions`.
+pub fn image_rustc_version(
+    image: &str,
+    mounts: &Mounts,
+    toolchain: &Version,
+    verbose: bool,
+) -> Result<Option<Version>> {
+    let id = engine_command(container_engine()?)
+        .args(["image", "inspect", "--format", "{{.Id}}", image])
+        .run_and_get_stdout(verbose);
+    // images that aren't cached yet are checked once they are
+    let id = match id {
+        Ok(id) => id.trim().to_string(),
+        Err(_) => return Ok(None),
+    };
+
+    let (cache, cached) = read_cache("image-rustc-versions");
+    if let Some(version) = cached_rustc_version(&cached, &id, toolchain) {
+        return Ok(version);
+    }
+
+    let mut docker = docker_command("run")?;
+    docker.arg("--rm");
+    for mount in mounts
+        .mounts
+        .iter()
+        .filter(|m| m.container == Path::new("/rust"))
+    {
+        docker.args(["-v", &mount.volume_arg()]);
+    }
+    let version = docker
+        .args([image, "sh", "-c", "PATH=$PATH:/rust/bin rustc --version"])
+        .run_and_get_stdout(verbose)
+        .ok()
+        .and_then(|output| parse_rustc_version(&output));
+
+    let entry = version.as_ref().map_or("-".to_string(), Version::to_string);
+    let cached = with_cache_entry(&cached, &id, &format!("{toolchain} {entry}"));
+    // entries of the images that have since been removed are dropped
+    let local = engine_command(container_engine()?)
+        .args(["image", "ls", "--quiet", "--no-trunc"])
+        .run_and_get_stdout(verbose)
+        .ok();
+    let cached = match local {
+        Some(local) => retain_cache_entries(&cached, |id| local_image(&local, id)),
+        None => cached,
+    };
+    write_cache(cache, &cached);
+
+    Ok(version)
+}
+
+/// Whether the image `id` is in the output of `image ls --quiet --no-trunc`,
+/// where Podman prefixes the IDs it otherwise reports bare with `sha256:`
+fn local_image(local: &str, id: &str) -> bool {
+    let bare = |id: &str| id.trim_start_matches("sha256:").to_string();
+    local.split_whitespace().any(|l| bare(l) == bare(id))
+}
+
+/// The cached version of the `rustc` in the image `id`, `Some(None)` if it
+/// has none, when it was found with the `toolchain` version
+fn cached_rustc_version(cache: &str, id: &str, toolchain: &Version) -> Option<Option<Version>> {
+    let (cached_toolchain, version) = cache_entry(cache, id)?.split_once(' ')?;
+    (cached_toolchain == toolchain.to_string()).then(|| Version::parse(version).ok())
+}
+
+/// Parses the output of `rustc --version`, like `rustc 1.62.0 (a8314ef7d 2022-06-27)`
+fn parse_rustc_version(output: &str) -> Option<Version> {
+    let version = output.trim().strip_prefix("rustc ")?;
+    Version::parse(version.split_whitespace().next()?).ok()
+}
+
+/// The warning for a custom image with another release of Rust than the
+/// toolchain it's used with, which tends to fail confusingly inside the container
+pub fn image_toolchain_mismatch(
+    image: &str,
+    image_version: &Version,
+    toolchain_version: &Version,
+) -> Option<String> {
+    let release = |v: &Version| (v.major, v.minor);
+    (release(image_version) != release(toolchain_version)).then(|| {
+        format!(
+            "image `{image}` was built with Rust {image_version}, but the toolchain is \
+             Rust {toolchain_version}, set `CROSS_NO_IMAGE_CHECK=1` if that's intended."
+        )
+    })
+}
+
+/// Whether an image is older than `max_age`, counting from when it was last
+/// pulled if that's known
+fn is_stale(
+    created: SystemTime,
+    pulled: Option<SystemTime>,
+    now: SystemTime,
+    max_age: Duration,
+) -> bool {
+    now.duration_since(pulled.unwrap_or(created))
+        .map(|age| age > max_age)
+        .unwrap_or(false)
+}
+
+/// Parses the `Created` timestamp of an image, as reported by either Docker
+/// (`2022-04-08T12:34:56.789Z`) or Podman (`2022-04-08 12:34:56.789 +0000 UTC`)
+fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    let timestamp = timestamp.trim();
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        timestamp.get(range).and_then(|s| s.parse().ok())
+    };
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+
+    let rest = timestamp.get(19..)?;
+    let rest = rest
+        .trim_start_matches(|c: char| c == '.' || c.is_ascii_digit())
+        .trim_start();
+    let offset = match rest.chars().next() {
+        Some('Z') => 0,
+        Some(sign @ ('+' | '-')) => {
+            let digits: String = rest[1..].chars().filter(|c| *c != ':').take(4).collect();
+            let hours: i64 = digits.get(0..2)?.parse().ok()?;
+            let minutes: i64 = digits.get(2..4)?.parse().ok()?;
+            let offset = hours * 3600 + minutes * 60;
+            if sign == '-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return None,
+    };
+
+    // days since the epoch of a proleptic Gregorian date
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(seconds).ok()?))
+}
+
+pub fn image(config: &Config, target: &Target, profile: &str) -> Result<String> {
+    if let Some(image) = config.image(target)? {
+        return expand_image(&image, profile);
+    }
+
+    if !DOCKER_IMAGES.contains(&target.triple()) {
+        bail!(
+            "`cross` does not provide a Docker image for target {target}, \
+               specify a custom image in `Cross.toml`."
+        );
+    }
+
+    let version = if include_str!(concat!(env!("OUT_DIR"), "/commit-info.txt")).is_empty() {
+        env!("CARGO_PKG_VERSION")
+    } else {
+        "main"
+    };
+
+    // a mirror keeps the names of the default images
+    let registry = config.image_registry();
+    let registry = registry
+        .as_deref()
+        .map_or(CROSS_IMAGE, |registry| registry.trim_end_matches('/'));
+
+    Ok(format!("{registry}/{target}:{version}"))
+}
+
+/// Replaces `{profile}` in a configured image with the profile of the build,
+/// `debug`, `release` or the name of a custom profile
+fn expand_image(image: &str, profile: &str) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = image;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| eyre::eyre!("unclosed `{{` in image `{image}`"))?;
+        expanded.push_str(&rest[..start]);
+        match &rest[start + 1..end] {
+            "profile" => expanded.push_str(profile),
+            other => bail!(
+                "unknown placeholder `{{{other}}}` in image `{image}`, only `{{profile}}` is supported"
+            ),
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(target_os = "windows")]
+fn wslpath(path: &Path, verbose: bool) -> Result<PathBuf> {
+    let wslpath = which::which("wsl.exe")
+        .map_err(|_| eyre::eyre!("could not find wsl.exe"))
+        .warning("usage of `env.volumes` requires WSL on Windows")
+        .suggestion("is WSL installed on the host?")?;
+
+    Command::new(wslpath)
+        .arg("-e")
+        .arg("wslpath")
+        .arg("-a")
+        .arg(path)
+        .run_and_get_stdout(verbose)
+        .wrap_err_with(|| {
+            format!(
+                "could not get linux compatible path for `{}`",
+                path.display()
+            )
+        })
+        .map(|s| s.trim().into())
+}
+
+/// Whether `cross` runs in the Windows Subsystem for Linux
+fn is_wsl() -> bool {
+    cfg!(target_os = "linux")
+        && fs::read_to_string("/proc/version").map_or(false, |version| {
+            version.to_lowercase().contains("microsoft")
+        })
+}
+
+/// Translates a WSL path to one a Windows container engine can mount: `C:/...`
+/// for the Windows drive at `/mnt/c/...`, and the `\\wsl$\...` share from
+/// `wslpath -w` for the WSL filesystem, like the cargo home or the toolchain
+fn wsl_windows_path(path: &Path, verbose: bool) -> Option<PathBuf> {
+    wsl_drive_path(path).or_else(|| {
+        Command::new("wslpath")
+            .arg("-w")
+            .arg(path)
+            .run_and_get_stdout(verbose)
+            .ok()
+            .map(|s| s.trim().into())
+            .filter(|s: &PathBuf| !s.as_os_str().is_empty())
+    })
+}
+
+/// Translates a WSL path of a Windows drive, `/mnt/c/...`, to the `C:/...` form
+fn wsl_drive_path(path: &Path) -> Option<PathBuf> {
+    let (drive, rest) = path
+        .strip_prefix("/mnt")
+        .ok()
+        .and_then(|rest| Some((rest.iter().next()?.to_str()?, rest)))
+        .filter(|(drive, _)| drive.len() == 1 && drive.chars().all(|c| c.is_ascii_alphabetic()))?;
+
+    let mut windows_path = format!("{}:", drive.to_ascii_uppercase());
+    for component in rest.iter().skip(1) {
+        windows_path.push('/');
+        windows_path.push_str(&component.to_string_lossy());
+    }
+    if windows_path.ends_with(':') {
+        windows_path.push('/');
+    }
+    Some(PathBuf::from(windows_path))
+}
+
+fn docker_read_mount_paths() -> Result<Vec<MountDetail>> {
+    let hostname = env::var("HOSTNAME").wrap_err("HOSTNAME environment variable not found")?;
+
+    let docker_path = which::which(DOCKER)?;
+    let mut docker: Command = {
+        let mut command = engine_command(docker_path);
+        command.arg("inspect");
+        command.arg(hostname);
+        command
+    };
+
+    let output = docker.run_and_get_stdout(false)?;
+    let info = serde_json::from_str(&output).wrap_err("failed to parse docker inspect output")?;
+    dockerinfo_parse_mounts(&info)
+}
+
+fn dockerinfo_parse_mounts(info: &serde_json::Value) -> Result<Vec<MountDetail>> {
+    let mut mounts = dockerinfo_parse_user_mounts(info);
+    let root_info = dockerinfo_parse_root_mount_path(info)?;
+    mounts.push(root_info);
+    Ok(mounts)
+}
+
+fn dockerinfo_parse_root_mount_path(info: &serde_json::Value) -> Result<MountDetail> {
+    let driver_name = info
+        .pointer("/0/GraphDriver/Name")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| eyre::eyre!("no driver name found"))?;
+
+    if driver_name == "overlay2" {
+        let path = info
+            .pointer("/0/GraphDriver/Data/MergedDir")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| eyre::eyre!("No merge directory found"))?;
+
+        Ok(MountDetail {
+            source: PathBuf::from(&path),
+            destination: PathBuf::from("/"),
+        })
+    } else {
+        eyre::bail!("want driver overlay2, got {driver_name}")
+    }
+}
+
+fn dockerinfo_parse_user_mounts(info: &serde_json::Value) -> Vec<MountDetail> {
+    info.pointer("/0/Mounts")
+        .and_then(|v| v.as_array())
+        .map(|v| {
+            let make_path = |v: &serde_json::Value| PathBuf::from(&v.as_str().unwrap());
+            let mut mounts = vec![];
+            for details in v {
+                let source = make_path(&details["Source"]);
+                let destination = make_path(&details["Destination"]);
+                mounts.push(MountDetail {
+                    source,
+                    destination,
+                });
+            }
+            mounts
+        })
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Default)]
+struct MountFinder {
+    mounts: Vec<MountDetail>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct MountDetail {
+    source: PathBuf,
+    destination: PathBuf,
+}
+
+impl MountFinder {
+    fn new(mounts: Vec<MountDetail>) -> MountFinder {
+        // sort by length (reverse), to give mounts with more path components a higher priority;
+        let mut mounts = mounts;
+        mounts.sort_by(|a, b| {
+            let la = a.destination.as_os_str().len();
+            let lb = b.destination.as_os_str().len();
+            la.cmp(&lb).reverse()
+        });
+        MountFinder { mounts }
+    }
+
+    fn find_mount_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+
+        for info in &self.mounts {
+            if let Ok(stripped) = path.strip_prefix(&info.destination) {
+                return info.source.join(stripped);
+            }
+        }
+
+        path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Environment;
+    use crate::CrossToml;
+
+    /// The config of `toml`, with only the `CROSS_*` variables of `env`
+    /// instead of the host's
+    fn config_for(toml: &str, env: &[(&'static str, &'static str)]) -> Result<Config> {
+        let env = Environment::new(Some(env.iter().copied().collect()));
+        Ok(Config::new_with(Some(CrossToml::parse(toml)?.0), env))
+    }
+
+    /// The arguments `f` adds to a `docker` command, with [`config_for`]
+    fn args_for(
+        toml: &str,
+        env: &[(&'static str, &'static str)],
+        f: impl FnOnce(&mut Command, &Config) -> Result<()>,
+    ) -> Result<Vec<String>> {
+        let config = config_for(toml, env)?;
+        let mut docker = Command::new(DOCKER);
+        f(&mut docker, &config)?;
+        Ok(docker
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect())
+    }
+
+    mod mount_finder {
+        use super::*;
+
+        #[test]
+        fn test_default_finder_returns_original() {
+            let finder = MountFinder::default();
+            assert_eq!(
+                PathBuf::from("/test/path"),
+                finder.find_mount_path("/test/path"),
+            );
+        }
+
+        #[test]
+        fn test_longest_destination_path_wins() {
+            let finder = MountFinder::new(vec![
+                MountDetail {
+                    source: PathBuf::from("/project/path"),
+                    destination: PathBuf::from("/project"),
+                },
+                MountDetail {
+                    source: PathBuf::from("/target/path"),
+                    destination: PathBuf::from("/project/target"),
+                },
+            ]);
+            assert_eq!(
+                PathBuf::from("/target/path/test"),
+                finder.find_mount_path("/project/target/test")
+            )
+        }
+
+        #[test]
+        fn test_adjust_multiple_paths() {
+            let finder = MountFinder::new(vec![
+                MountDetail {
+                    source: PathBuf::from("/var/lib/docker/overlay2/container-id/merged"),
+                    destination: PathBuf::from("/"),
+                },
+                MountDetail {
+                    source: PathBuf::from("/home/project/path"),
+                    destination: PathBuf::from("/project"),
+                },
+            ]);
+            assert_eq!(
+                PathBuf::from("/var/lib/docker/overlay2/container-id/merged/container/path"),
+                finder.find_mount_path("/container/path")
+            );
+            assert_eq!(
+                PathBuf::from("/home/project/path"),
+                finder.find_mount_path("/project")
+            );
+            assert_eq!(
+                PathBuf::from("/home/project/path/target"),
+                finder.find_mount_path("/project/target")
+            );
+        }
+    }
+
+    mod mounts {
+        use super::*;
+        use serde_json::json;
+
+        #[test]
+        fn test_workdir_follows_cwd() -> Result<()> {
+            let project = Path::new("/project");
+            let root = Path::new("/home/user/workspace");
+            assert_eq!(container_workdir(project, root, root, None)?, project);
+            assert_eq!(
+                container_workdir(project, root, &root.join("crates/member"), None)?,
+                Path::new("/project/crates/member")
+            );
+            // run from outside the project with `--manifest-path`
+            assert_eq!(
+                container_workdir(project, root, Path::new("/tmp"), None)?,
+                project
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn test_configured_workdir() -> Result<()> {
+            let project = Path::new("/project");
+            let root = Path::new("/home/user/workspace");
+            let cwd = root.join("crates/member");
+            assert_eq!(
+                container_workdir(project, root, &cwd, Some("crates/cli"))?,
+                Path::new("/project/crates/cli")
+            );
+            assert_eq!(container_workdir(project, root, &cwd, Some("."))?, project);
+            assert!(container_workdir(project, root, &cwd, Some("../elsewhere")).is_err());
+            assert!(container_workdir(project, root, &cwd, Some("/elsewhere")).is_err());
+            Ok(())
+        }
+
+        fn mounts_with_cargo_home(cargo_home: &str) -> Mounts {
+            let workdir = PathBuf::from("/project");
+            let cargo_home = PathBuf::from(cargo_home);
+            let mut mounts =
+                cargo_mounts(Path::new("/home/user/.cargo"), &cargo_home, MountMode::Rw);
+            mounts.extend(standard_mounts(
+                PathBuf::from("/home/user/.xargo"),
+                PathBuf::from("/home/user/project"),
+                &workdir,
+                MountMode::Rw,
+                PathBuf::from("/home/user/.rustup/toolchains/stable-x86_64-unknown-linux-gnu"),
+            ));
+            Mounts {
+                mounts,
+                workdir,
+                cargo_home,
+                copy_artifacts_to: None,
+                project: PathBuf::from("/home/user/project"),
+            }
+        }
+
+        fn mounts() -> Mounts {
+            mounts_with_cargo_home("/cargo")
+        }
+
+        #[test]
+        fn test_emitted_project_and_sysroot_mounts() {
+            let emitted = serde_json::to_value(mounts()).unwrap();
+            let mounts = emitted["mounts"].as_array().unwrap();
+            assert!(mounts.contains(&json!({
+                "host": "/home/user/project",
+                "container": "/project",
+                "read_only": false,
+                "env": null,
+            })));
+            assert!(mounts.contains(&json!({
+                "host": "/home/user/.rustup/toolchains/stable-x86_64-unknown-linux-gnu",
+                "container": "/rust",
+                "read_only": true,
+                "env": null,
+            })));
+            assert_eq!(emitted["workdir"], "/project");
+        }
+
+        #[test]
+        fn test_host_path() {
+            let mut mounts = mounts();
+            mounts
+                .mounts
+                .push(Mount::new("/home/user/project/target", "/target"));
+            assert_eq!(
+                mounts.host_path(Path::new("/target/debug/deps/hello")),
+                Some(PathBuf::from("/home/user/project/target/debug/deps/hello"))
+            );
+            assert_eq!(mounts.host_path(Path::new("/usr/bin/cc")), None);
+
+            let copied = Mounts {
+                copy_artifacts_to: Some(PathBuf::from("/tmp/target")),
+                ..mounts_with_cargo_home("/cargo")
+            };
+            assert_eq!(
+                copied.host_path(Path::new("/target/debug/hello")),
+                Some(PathBuf::from("/tmp/target/debug/hello"))
+            );
+        }
+
+        #[test]
+        fn test_volume_args() {
+            let mut docker = Command::new(DOCKER);
+            docker_mounts(&mut docker, &mounts());
+            let args: Vec<_> = docker.get_args().collect();
+            assert!(args.contains(&"/home/user/project:/project:Z".as_ref()));
+            assert!(args.contains(
+                &"/home/user/.rustup/toolchains/stable-x86_64-unknown-linux-gnu:/rust:Z,ro"
+                    .as_ref()
+            ));
+            assert!(args.ends_with(&["-w".as_ref(), "/project".as_ref()]));
+        }
+
+        #[test]
+        fn test_registry_mounts() {
+            let cargo_dir = Path::new("/home/user/.cargo");
+            let cargo_home = Path::new("/cargo");
+            let volume_args = |mode| {
+                cargo_mounts(cargo_dir, cargo_home, mode)
+                    .iter()
+                    .map(|m| m.volume_arg())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(volume_args(MountMode::Rw), ["/home/user/.cargo:/cargo:Z"]);
+            // the git caches stay writable with the rest of the cargo home
+            assert_eq!(
+                volume_args(MountMode::Ro),
+                [
+                    "/home/user/.cargo:/cargo:Z",
+                    "/home/user/.cargo/registry:/cargo/registry:Z,ro",
+                ]
+            );
+        }
+
+        #[test]
+        fn test_read_only_source() {
+            let volume_args = |mode| {
+                standard_mounts(
+                    PathBuf::from("/home/user/.xargo"),
+                    PathBuf::from("/home/user/project"),
+                    Path::new("/project"),
+                    mode,
+                    PathBuf::from("/rust-sysroot"),
+                )
+                .iter()
+                .map(|m| m.volume_arg())
+                .collect::<Vec<_>>()
+            };
+            assert!(volume_args(MountMode::Rw).contains(&"/home/user/project:/project:Z".into()));
+            // the xargo home stays writable, and the target directory is mounted apart
+            assert_eq!(
+                volume_args(MountMode::Ro),
+                [
+                    "/home/user/.xargo:/xargo:Z",
+                    "/home/user/project:/project:Z,ro",
+                    "/rust-sysroot:/rust:Z,ro",
+                ]
+            );
+        }
+
+        #[test]
+        fn test_custom_cargo_home() {
+            let mounts = mounts_with_cargo_home("/opt/cargo");
+            let mut docker = Command::new(DOCKER);
+            docker_mounts(&mut docker, &mounts);
+            let args: Vec<_> = docker.get_args().collect();
+            assert!(args.contains(&"/home/user/.cargo:/opt/cargo:Z".as_ref()));
+            assert!(args.contains(&"/opt/cargo/bin".as_ref()));
+            assert!(!args.contains(&"/cargo/bin".as_ref()));
+
+            let registry = cargo_mounts(
+                Path::new("/home/user/.cargo"),
+                &mounts.cargo_home,
+                MountMode::Ro,
+            );
+            assert_eq!(
+                registry[1].volume_arg(),
+                "/home/user/.cargo/registry:/opt/cargo/registry:Z,ro"
+            );
+        }
+
+        #[test]
+        fn test_isolated_target_dir() {
+            let root = Path::new("/home/user/project");
+            let target = Target::new_built_in("aarch64-unknown-linux-gnu");
+            assert_eq!(
+                host_target_dir(&None, root, &target, false),
+                Path::new("/home/user/project/target")
+            );
+
+            let target_dir = host_target_dir(&None, root, &target, true);
+            assert_eq!(
+                target_dir,
+                Path::new("/home/user/project/target/aarch64-unknown-linux-gnu")
+            );
+            assert_eq!(
+                Mount::new(target_dir, "/target").volume_arg(),
+                "/home/user/project/target/aarch64-unknown-linux-gnu:/target:Z"
+            );
+
+            let target_dir = Some(PathBuf::from("/tmp/target"));
+            assert_eq!(
+                host_target_dir(&target_dir, root, &target, true),
+                Path::new("/tmp/target/aarch64-unknown-linux-gnu")
+            );
+        }
+
+        #[test]
+        fn test_env_volume_is_not_relabeled() {
+            let mount = Mount {
+                env: Some("BUILD_DIR".to_string()),
+                relabel: false,
+                ..Mount::new("/data/build", "/data/build")
+            };
+            assert_eq!(mount.volume_arg(), "/data/build:/data/build");
+        }
+    }
+
+    mod copy_artifacts {
+        use super::*;
+
+        fn args(command: &Command) -> Vec<String> {
+            command
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect()
+        }
+
+        #[test]
+        fn test_command_sequence() {
+            let [cp_in, start, cp, rm] = copy_artifacts_commands(
+                Path::new(DOCKER),
+                "cross-aarch64-unknown-linux-gnu-42",
+                Path::new("/home/user/project/target"),
+                false,
+            );
+            assert_eq!(
+                args(&cp_in),
+                [
+                    "cp",
+                    "--archive",
+                    "/home/user/project/target/.",
+                    "cross-aarch64-unknown-linux-gnu-42:/target"
+                ]
+            );
+            assert_eq!(
+                args(&start),
+                ["start", "--attach", "cross-aarch64-unknown-linux-gnu-42"]
+            );
+            assert_eq!(
+                args(&cp),
+                [
+                    "cp",
+                    "cross-aarch64-unknown-linux-gnu-42:/target/.",
+                    "/home/user/project/target"
+                ]
+            );
+            assert_eq!(args(&rm), ["rm", "cross-aarch64-unknown-linux-gnu-42"]);
+        }
+
+        #[test]
+        fn test_interactive_start() {
+            let [_, start, ..] =
+                copy_artifacts_commands(Path::new(DOCKER), "name", Path::new("target"), true);
+            assert_eq!(args(&start), ["start", "--attach", "--interactive", "name"]);
+        }
+    }
+
+    mod image_profile {
+        use super::*;
+        use crate::cli::parse_args;
+        use crate::rustc::TargetList;
+
+        #[test]
+        fn test_expand_profile() -> Result<()> {
+            let toml = r#"
+                [target.aarch64-unknown-linux-gnu]
+                image = "my/image:{profile}"
+            "#;
+            let config = config_for(toml, &[])?;
+            let target = Target::new_built_in("aarch64-unknown-linux-gnu");
+            let target_list = TargetList { triples: vec![] };
+            let profile = |args: &[&str]| {
+                parse_args(args.iter().map(|a| a.to_string()), &target_list).profile()
+            };
+
+            assert_eq!(
+                image(&config, &target, &profile(&["build"]))?,
+                "my/image:debug"
+            );
+            assert_eq!(
+                image(&config, &target, &profile(&["build", "--release"]))?,
+                "my/image:release"
+            );
+            assert_eq!(
+                image(&config, &target, &profile(&["build", "--profile=dist"]))?,
+                "my/image:dist"
+            );
+            assert_eq!(
+                image(&config, &target, &profile(&["run", "--", "--release"]))?,
+                "my/image:debug"
+            );
+
+            let err = expand_image("my/image:{target}", "debug").unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "unknown placeholder `{target}` in image `my/image:{target}`, only `{profile}` is supported"
+            );
+            Ok(())
+        }
+    }
+
+    mod image_registry {
+        use super::*;
+
+        #[test]
+        fn test_prefix_default_images() -> Result<()> {
+            let toml = r#"
+                [build]
+                image-registry = "registry.corp/cross/"
+
+                [target.armv7-unknown-linux-gnueabihf]
+                image = "ghcr.io/cross-rs/armv7-unknown-linux-gnueabihf:edge"
+            "#;
+            let config = config_for(toml, &[])?;
+
+            let default = image(
+                &config,
+                &Target::new_built_in("aarch64-unknown-linux-gnu"),
+                "debug",
+            )?;
+            assert!(
+                default.starts_with("registry.corp/cross/aarch64-unknown-linux-gnu:"),
+                "{default}"
+            );
+            let explicit = image(
+                &config,
+                &Target::new_built_in("armv7-unknown-linux-gnueabihf"),
+                "debug",
+            )?;
+            assert_eq!(
+                explicit,
+                "ghcr.io/cross-rs/armv7-unknown-linux-gnueabihf:edge"
+            );
+
+            let default = image(
+                &config_for("", &[])?,
+                &Target::new_built_in("aarch64-unknown-linux-gnu"),
+                "debug",
+            )?;
+            assert!(default.starts_with("ghcr.io/cross-rs/aarch64-unknown-linux-gnu:"));
+            Ok(())
+        }
+    }
+
+    mod image_age {
+        use super::*;
+
+        const DAY: u64 = 24 * 60 * 60;
+
+        #[test]
+        fn test_parse_docker_timestamp() {
+            assert_eq!(
+                parse_timestamp("2022-04-08T12:34:56.123456789Z\n"),
+                Some(UNIX_EPOCH + Duration::from_secs(1649421296))
+            );
+        }
+
+        #[test]
+        fn test_parse_podman_timestamp() {
+            assert_eq!(
+                parse_timestamp("2022-04-08 14:34:56.123456789 +0200 CEST"),
+                Some(UNIX_EPOCH + Duration::from_secs(1649421296))
+            );
+        }
+
+        #[test]
+        fn test_parse_invalid_timestamp() {
+            assert_eq!(parse_timestamp("<no value>"), None);
+        }
+
+        #[test]
+        fn test_repull_decision() {
+            let created = parse_timestamp("2022-04-08T12:34:56Z").unwrap();
+            let max_age = Duration::from_secs(7 * DAY);
+            let now = created + Duration::from_secs(6 * DAY);
+            assert!(!is_stale(created, None, now, max_age));
+            let now = created + Duration::from_secs(8 * DAY);
+            assert!(is_stale(created, None, now, max_age));
+
+            // an old image that was pulled recently
+            let created = parse_timestamp("2021-01-01T00:00:00Z").unwrap();
+            let pulled = now - Duration::from_secs(DAY);
+            assert!(!is_stale(created, Some(pulled), now, max_age));
+            let pulled = now - Duration::from_secs(8 * DAY);
+            assert!(is_stale(created, Some(pulled), now, max_age));
+        }
+
+        #[test]
+        fn test_cache_entries() {
+            let cache = "ubuntu:20.04 1649421296\nmy/image 1649000000\n";
+            assert_eq!(cache_entry(cache, "my/image"), Some("1649000000"));
+            assert_eq!(cache_entry(cache, "my/other"), None);
+            assert_eq!(
+                with_cache_entry(cache, "ubuntu:20.04", "1650000000"),
+                "my/image 1649000000\nubuntu:20.04 1650000000\n"
+            );
+        }
+    }
+
+    mod user_docker_args {
+        use super::*;
+
+        #[test]
+        fn test_append_lines() -> Result<()> {
+            let path = env::temp_dir().join(format!("cross-docker-args-{}", std::process::id()));
+            fs::write(&path, "# local tweaks\n--cpus 2\n\n--network=host\n")?;
+            let mut docker = Command::new(DOCKER);
+            docker.args(["-w", "/project"]);
+            let result = docker_user_args(&mut docker, &path);
+            fs::remove_file(&path).ok();
+            result?;
+
+            let args: Vec<_> = docker.get_args().collect();
+            assert_eq!(args, ["-w", "/project", "--cpus", "2", "--network=host"]);
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_missing_file() -> Result<()> {
+            let mut docker = Command::new(DOCKER);
+            docker_user_args(&mut docker, Path::new("/nonexistent/cross/docker-args"))?;
+            assert_eq!(docker.get_args().count(), 0);
+
+            Ok(())
+        }
+    }
+
+    mod extra_args {
+        use super::*;
+
+        #[test]
+        fn test_before_image() -> Result<()> {
+            let toml = r#"
+                [build]
+                docker-args = ["--shm-size=1g"]
+
+                [target.aarch64-unknown-linux-gnu]
+                docker-args = ["--device", "/dev/fuse"]
+            "#;
+            let args = args_for(toml, &[], |docker, config| {
+                docker.args(["--rm", "-w", "/project"]);
+                docker_extra_args(
+                    docker,
+                    config,
+                    &Target::new_built_in("aarch64-unknown-linux-gnu"),
+                    None,
+                    &["--cap-add=SYS_PTRACE".to_string()],
+                )?;
+                docker.arg("ghcr.io/cross-rs/aarch64-unknown-linux-gnu:main");
+                Ok(())
+            })?;
+            assert_eq!(
+                args,
+                [
+                    "--rm",
+                    "-w",
+                    "/project",
+                    "--shm-size=1g",
+                    "--device",
+                    "/dev/fuse",
+                    "--cap-add=SYS_PTRACE",
+                    "ghcr.io/cross-rs/aarch64-unknown-linux-gnu:main",
+                ]
+            );
+
+            Ok(())
+        }
+    }
+
+    mod env_file {
+        use super::*;
+        use std::ffi::OsStr;
+
+        #[test]
+        fn test_pass_by_name() -> Result<()> {
+            let vars = crate::env_file::parse(
+                "# secrets\nCROSS_TEST_ENV_FILE_TOKEN=\"s3cret\"\nCARGO_PKG_NAME=ignored\n",
+            )?;
+            let mut docker = Command::new(DOCKER);
+            docker_env_file(&mut docker, &vars, false)?;
+
+            let args: Vec<_> = docker.get_args().collect();
+            assert_eq!(
+                args,
+                ["-e", "CROSS_TEST_ENV_FILE_TOKEN", "-e", "CARGO_PKG_NAME"]
+            );
+            let envs: Vec<_> = docker.get_envs().collect();
+            assert_eq!(
+                envs,
+                [(
+                    OsStr::new("CROSS_TEST_ENV_FILE_TOKEN"),
+                    Some(OsStr::new("s3cret"))
+                )]
+            );
+
+            Ok(())
+        }
+    }
+
+    mod zig {
+        use super::*;
+
+        #[test]
+        fn test_zig_targets() -> Result<()> {
+            let target = Target::new_built_in("x86_64-unknown-linux-gnu");
+            assert_eq!(zig_target(&target, "zig")?, "x86_64-linux-gnu");
+            assert_eq!(zig_target(&target, "zig.2.17")?, "x86_64-linux-gnu.2.17");
+
+            let target = Target::new_built_in("armv7-unknown-linux-gnueabihf");
+            assert_eq!(zig_target(&target, "zig")?, "arm-linux-gnueabihf");
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_unsupported_zig_targets() {
+            let musl = Target::new_built_in("x86_64-unknown-linux-musl");
+            assert!(zig_target(&musl, "zig.2.17").is_err());
+            let windows = Target::new_built_in("x86_64-pc-windows-gnu");
+            assert!(zig_target(&windows, "zig").is_err());
+            let linux = Target::new_built_in("x86_64-unknown-linux-gnu");
+            assert!(zig_target(&linux, "lld").is_err());
+        }
+
+        #[test]
+        fn test_wrapper_script() {
+            let setup = zig_cc_setup("x86_64-linux-gnu.2.17");
+            assert!(setup.contains("command -v zig"));
+            assert!(setup.contains(
+                r#"printf '#!/bin/sh\nexec zig cc -target x86_64-linux-gnu.2.17 "$@"\n' > /tmp/cross-zig-cc"#
+            ));
+        }
+    }
+
+    mod target_tools {
+        use super::*;
+
+        #[test]
+        fn test_target_var() {
+            let target = Target::new_built_in("armv7-unknown-linux-gnueabihf");
+            assert_eq!(
+                target_var(&target, "LINKER"),
+                "CARGO_TARGET_ARMV7_UNKNOWN_LINUX_GNUEABIHF_LINKER"
+            );
+            let target = Target::new_built_in("thumbv8m.main-none-eabi");
+            assert_eq!(
+                target_var(&target, "RUNNER"),
+                "CARGO_TARGET_THUMBV8M_MAIN_NONE_EABI_RUNNER"
+            );
+        }
+
+        #[test]
+        fn test_configured_tools() -> Result<()> {
+            let toml = r#"
+                [target.aarch64-unknown-linux-gnu]
+                linker = "aarch64-linux-gnu-gcc-12"
+                ar = "aarch64-linux-gnu-ar"
+                runner = "qemu-aarch64 -L /usr/aarch64-linux-gnu"
+
+                [target.x86_64-unknown-linux-gnu]
+                linker = "zig"
+                runner = "native"
+            "#;
+            let args = |triple: &str| {
+                args_for(toml, &[], |docker, config| {
+                    docker_target_tools(docker, config, &Target::new_built_in(triple))
+                })
+            };
+
+            assert_eq!(
+                args("aarch64-unknown-linux-gnu")?,
+                [
+                    "-e",
+                    "CARGO_TARGET_AARCH64_UNKNOWN_LINUX_GNU_LINKER=aarch64-linux-gnu-gcc-12",
+                    "-e",
+                    "CARGO_TARGET_AARCH64_UNKNOWN_LINUX_GNU_AR=aarch64-linux-gnu-ar",
+                    "-e",
+                    "CARGO_TARGET_AARCH64_UNKNOWN_LINUX_GNU_RUNNER=qemu-aarch64 -L /usr/aarch64-linux-gnu",
+                ]
+            );
+            // `zig` and the runners of the images are handled by `cross`
+            assert!(args("x86_64-unknown-linux-gnu")?.is_empty());
+            Ok(())
+        }
+    }
+
+    mod sccache {
+        use super::*;
+
+        #[test]
+        fn test_sccache_wiring() -> Result<()> {
+            let toml = r#"
+                [build]
+                sccache = true
+            "#;
+            let mounts = Mounts {
+                workdir: PathBuf::from("/project"),
+                cargo_home: PathBuf::from("/cargo"),
+                mounts: vec![sccache_mount(PathBuf::from("/home/user/.cache/sccache"))],
+                copy_artifacts_to: None,
+                project: PathBuf::from("/home/user/project"),
+            };
+
+            let args = args_for(toml, &[], |docker, config| {
+                assert!(docker_sccache(docker, config)?);
+                docker_mounts(docker, &mounts);
+                Ok(())
+            })?;
+            assert_eq!(
+                &args[..6],
+                [
+                    "-e",
+                    "RUSTC_WRAPPER=sccache",
+                    "-v",
+                    "/home/user/.cache/sccache:/sccache:Z",
+                    "-e",
+                    "SCCACHE_DIR=/sccache",
+                ]
+            );
+
+            let args = args_for("", &[], |docker, config| {
+                assert!(!docker_sccache(docker, config)?);
+                Ok(())
+            })?;
+            assert!(args.is_empty());
+            Ok(())
+        }
+    }
+
+    mod incremental {
+        use super::*;
+
+        fn args(toml: &str) -> Result<Vec<String>> {
+            args_for(toml, &[], docker_incremental)
+        }
+
+        #[test]
+        fn test_incremental_from_config() -> Result<()> {
+            assert_eq!(
+                args("[build]\nincremental = false\n")?,
+                ["-e", "CARGO_INCREMENTAL=0"]
+            );
+            assert_eq!(
+                args("[build]\nincremental = true\n")?,
+                ["-e", "CARGO_INCREMENTAL=1"]
+            );
+            assert!(args("")?.is_empty());
+            Ok(())
+        }
+    }
+
+    mod network {
+        use super::*;
+
+        fn network_args(toml: &str) -> Result<Vec<String>> {
+            args_for(toml, &[], |docker, config| {
+                docker_network(docker, config, &["build".to_string()]);
+                Ok(())
+            })
+        }
+
+        #[test]
+        fn test_network_from_config() -> Result<()> {
+            let args = network_args(
+                r#"
+                [build]
+                network = "host"
+            "#,
+            )?;
+            assert_eq!(args, ["--network", "host"]);
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_default_network() -> Result<()> {
+            assert!(network_args("")?.is_empty());
+
+            Ok(())
+        }
+    }
+
+    mod hostname {
+        use super::*;
+
+        fn hostname_args(toml: &str) -> Result<Vec<String>> {
+            args_for(toml, &[], docker_hostname)
+        }
+
+        #[test]
+        fn test_hostname_from_config() -> Result<()> {
+            let args = hostname_args(
+                r#"
+                [build]
+                hostname = "ci-builder.local"
+            "#,
+            )?;
+            assert_eq!(args, ["--hostname", "ci-builder.local"]);
+            assert!(hostname_args("")?.is_empty());
+
+            let env = [("CROSS_BUILD_HOSTNAME", "from-env.local")];
+            assert_eq!(
+                args_for("[build]\nhostname = \"builder\"", &env, docker_hostname)?,
+                ["--hostname", "from-env.local"]
+            );
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_invalid_hostname() {
+            for hostname in ["under_score", "-leading", "trailing.", ""] {
+                let toml = format!("[build]\nhostname = \"{hostname}\"");
+                assert!(hostname_args(&toml).is_err(), "{hostname}");
+            }
+            let host_network = r#"
+                [build]
+                hostname = "builder"
+                network = "host"
+            "#;
+            assert!(hostname_args(host_network).is_err());
+        }
+    }
+
+    mod android_api {
+        use super::*;
+
+        #[test]
+        fn test_export_from_config() -> Result<()> {
+            let toml = r#"
+                [target.armv7-linux-androideabi]
+                android-api = 24
+            "#;
+            let args = args_for(toml, &[], |docker, config| {
+                docker_android_api(
+                    docker,
+                    config,
+                    &Target::new_built_in("armv7-linux-androideabi"),
+                )
+            })?;
+            assert_eq!(args, ["-e", "ANDROID_API=24"]);
+            Ok(())
+        }
+    }
+
+    mod cargo_command {
+        use super::*;
+
+        #[test]
+        fn test_configured_cargo() -> Result<()> {
+            let target = Target::new_built_in("aarch64-unknown-linux-gnu");
+            let args = ["build".to_string()];
+            let config = config_for("", &[])?;
+            assert_eq!(
+                format!("{:?}", cargo_command(&config, &target, &args, false)),
+                "cargo build"
+            );
+
+            let toml = r#"
+                [build]
+                cargo = "cargo-zigbuild"
+            "#;
+            let config = config_for(toml, &[])?;
+            assert_eq!(
+                format!("{:?}", cargo_command(&config, &target, &args, false)),
+                "cargo-zigbuild build"
+            );
+            assert_eq!(
+                format!("{:?}", cargo_command(&config, &target, &args, true)),
+                "xargo build"
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn test_runner_wrapper() -> Result<()> {
+            let target = Target::new_built_in("aarch64-unknown-linux-gnu");
+            let args = ["build".to_string(), "--release".to_string()];
+            let toml = r#"
+                [target.aarch64-unknown-linux-gnu]
+                runner-wrapper = ["/setup.sh", "--display", ":99"]
+            "#;
+            let config = config_for(toml, &[])?;
+            assert_eq!(
+                format!("{:?}", cargo_command(&config, &target, &args, false)),
+                "/setup.sh --display ':99' -- cargo build --release"
+            );
+
+            let other = Target::new_built_in("x86_64-unknown-linux-gnu");
+            assert_eq!(
+                format!("{:?}", cargo_command(&config, &other, &args, false)),
+                "cargo build --release"
+            );
+            Ok(())
+        }
+    }
+
+    mod shell {
+        use super::*;
+
+        #[test]
+        fn test_shell_command() {
+            let mut docker = Command::new(DOCKER);
+            docker.args(["run", "--rm", "-i", "-t"]);
+            shell_command(
+                &mut docker,
+                "ghcr.io/cross-rs/aarch64-unknown-linux-gnu:main",
+            );
+            let args: Vec<_> = docker.get_args().collect();
+            assert_eq!(
+                args,
+                [
+                    "run",
+                    "--rm",
+                    "-i",
+                    "-t",
+                    "--entrypoint",
+                    "/bin/sh",
+                    "ghcr.io/cross-rs/aarch64-unknown-linux-gnu:main",
+                    "-c",
+                    "export PATH=$PATH:/rust/bin && exec /bin/sh -i",
+                ]
+            );
+        }
+    }
+
+    mod post_build {
+        use super::*;
+
+        fn strings(args: &[&str]) -> Vec<String> {
+            args.iter().map(|a| a.to_string()).collect()
+        }
+
+        #[test]
+        fn test_post_build_hooks() -> Result<()> {
+            let toml = r#"
+                [target.aarch64-unknown-linux-gnu]
+                post-build = ["strip {bin}"]
+            "#;
+            let config = config_for(toml, &[])?;
+            let target = Target::new_built_in("aarch64-unknown-linux-gnu");
+            for sc in ["build", "b"] {
+                assert_eq!(
+                    post_build_hooks(&config, &target, Some(Subcommand::from(sc))),
+                    ["strip {bin}"]
+                );
+            }
+            assert!(post_build_hooks(&config, &target, Some(Subcommand::Test)).is_empty());
+            assert!(post_build_hooks(&config, &target, None).is_empty());
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_post_build_args() -> Result<()> {
+            let (args, forward) = post_build_args(&strings(&["build", "--release", "--", "-v"]))?;
+            assert_eq!(
+                args,
+                [
+                    "build",
+                    "--release",
+                    "--message-format=json-render-diagnostics",
+                    "--",
+                    "-v"
+                ]
+            );
+            assert!(!forward);
+
+            let asked = strings(&["build", "--message-format", "json"]);
+            assert_eq!(post_build_args(&asked)?, (asked, true));
+
+            assert!(post_build_args(&strings(&["build", "--message-format=short"])).is_err());
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_post_build_script() {
+            let hooks = strings(&["strip {bin}", "upx --best {bin}", "ls /target"]);
+            assert_eq!(
+                post_build_script("cargo build", &hooks, false),
+                "cargo build > /tmp/cross-post-build.json \
+                 && grep -o '\"executable\":\"[^\"]*\"' /tmp/cross-post-build.json | cut -d '\"' -f 4 | \
+                 while IFS= read -r bin; do strip \"$bin\" || exit $?; done \
+                 && grep -o '\"executable\":\"[^\"]*\"' /tmp/cross-post-build.json | cut -d '\"' -f 4 | \
+                 while IFS= read -r bin; do upx --best \"$bin\" || exit $?; done \
+                 && ls /target"
+            );
+
+            // the messages asked for are printed, whether the build succeeds or not
+            assert_eq!(
+                post_build_script("cargo build", &strings(&["ls /target"]), true),
+                "(cargo build > /tmp/cross-post-build.json; code=$?; \
+                 cat /tmp/cross-post-build.json; exit $code) && ls /target"
+            );
+        }
+    }
+
+    mod removal {
+        use super::*;
+
+        fn args(removed_separately: bool) -> Vec<String> {
+            let mut docker = Command::new(DOCKER);
+            docker_removal(&mut docker, "cross-build", removed_separately);
+            docker
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect()
+        }
+
+        #[test]
+        fn test_keep_container_on_failure() -> Result<()> {
+            let status = |code: i32| -> Result<ExitStatus> {
+                Ok(Command::new("sh")
+                    .args(["-c", &format!("exit {code}")])
+                    .status()?)
+            };
+
+            assert_eq!(args(false), ["--rm"]);
+            assert!(!keeps_container(false, &status(1)));
+
+            // the container is named instead, and removed unless the build failed
+            assert_eq!(args(true), ["--name", "cross-build"]);
+            assert!(keeps_container(true, &status(1)));
+            assert!(keeps_container(true, &Err(eyre::eyre!("killed"))));
+            assert!(!keeps_container(true, &status(0)));
+            Ok(())
+        }
+    }
+
+    mod passthrough {
+        use super::*;
+
+        #[test]
+        fn test_trailing_wildcards() {
+            let passthrough = [
+                "CI_*".to_string(),
+                "TOKEN".to_string(),
+                "CI_JOB_ID".to_string(),
+            ];
+            let host = ["PATH", "CI_JOB_ID", "CI_COMMIT_SHA", "HOME", "CIRCLECI"];
+            let expanded = expand_passthrough(&passthrough, host.iter().map(|v| v.to_string()));
+            assert_eq!(expanded, ["CI_COMMIT_SHA", "CI_JOB_ID", "TOKEN"]);
+
+            assert!(expand_passthrough(&["CI_*".to_string()], vec![]).is_empty());
+        }
 
-fn dockerinfo_parse_root_mount_path(info: &serde_json::Value) -> Result<MountDetail> {
-    let driver_name = info
-        .pointer("/0/GraphDriver/Name")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| eyre::eyre!("no driver name found"))?;
+        #[test]
+        fn test_invalid_patterns() {
+            assert!(validate_env_var("CI_*").is_ok());
+            assert!(validate_env_var("*").is_err());
+            assert!(validate_env_var("CI_*_ID").is_err());
+        }
+    }
 
-    if driver_name == "overlay2" {
-        let path = info
-            .pointer("/0/GraphDriver/Data/MergedDir")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| eyre::eyre!("No merge directory found"))?;
+    mod proxy {
+        use super::*;
 
-        Ok(MountDetail {
-            source: PathBuf::from(&path),
-            destination: PathBuf::from("/"),
-        })
-    } else {
-        eyre::bail!("want driver overlay2, got {driver_name}")
+        #[test]
+        fn test_forward_proxy_vars_set_on_host() {
+            let host = ["HTTPS_PROXY", "no_proxy", "http_proxy"];
+            let is_set = |var: &str| host.contains(&var);
+            assert_eq!(
+                proxy_vars(&[], is_set),
+                ["HTTPS_PROXY", "http_proxy", "no_proxy"]
+            );
+            assert_eq!(
+                proxy_vars(&["http_proxy".to_string()], is_set),
+                ["HTTPS_PROXY", "no_proxy"]
+            );
+            assert!(proxy_vars(&[], |_| false).is_empty());
+        }
+
+        #[test]
+        fn test_opt_out() -> Result<()> {
+            assert!(config_for("", &[])?.forward_proxy()?);
+            let toml = r#"
+                [build]
+                forward-proxy = false
+            "#;
+            let config = config_for(toml, &[])?;
+            assert!(!config.forward_proxy()?);
+            Ok(())
+        }
     }
-}
 
-fn dockerinfo_parse_user_mounts(info: &serde_json::Value) -> Vec<MountDetail> {
-    info.pointer("/0/Mounts")
-        .and_then(|v| v.as_array())
-        .map(|v| {
-            let make_path = |v: &serde_json::Value| PathBuf::from(&v.as_str().unwrap());
-            let mut mounts = vec![];
-            for details in v {
-                let source = make_path(&details["Source"]);
-                let destination = make_path(&details["Destination"]);
-                mounts.push(MountDetail {
-                    source,
-                    destination,
-                });
+    mod resources {
+        use super::*;
+
+        fn args(toml: &str) -> Result<Vec<String>> {
+            args_for(toml, &[], |docker, config| {
+                docker_resources(
+                    docker,
+                    config,
+                    &Target::new_built_in("aarch64-unknown-linux-gnu"),
+                )
+            })
+        }
+
+        #[test]
+        fn test_unlimited_by_default() -> Result<()> {
+            assert!(args("")?.is_empty());
+            Ok(())
+        }
+
+        #[test]
+        fn test_limits_from_config() -> Result<()> {
+            let toml = r#"
+                [build]
+                memory = "8g"
+                cpus = "2"
+
+                [target.aarch64-unknown-linux-gnu]
+                memory = "4g"
+            "#;
+            assert_eq!(args(toml)?, ["--memory", "4g", "--cpus", "2"]);
+            Ok(())
+        }
+
+        #[test]
+        fn test_invalid_limits() {
+            for toml in [
+                "[build]\nmemory = \"lots\"",
+                "[build]\nmemory = \"4gb\"",
+                "[build]\nmemory = \"0\"",
+                "[build]\ncpus = \"two\"",
+                "[build]\ncpus = \"-1\"",
+            ] {
+                assert!(args(toml).is_err(), "{toml}");
             }
-            mounts
-        })
-        .unwrap_or_else(Vec::new)
-}
+        }
+    }
 
-#[derive(Debug, Default)]
-struct MountFinder {
-    mounts: Vec<MountDetail>,
-}
+    mod privileged {
+        use super::*;
 
-#[derive(Debug, Clone, PartialEq)]
-struct MountDetail {
-    source: PathBuf,
-    destination: PathBuf,
-}
+        fn args(toml: &str, triple: &str) -> Result<Vec<String>> {
+            args_for(toml, &[], |docker, config| {
+                docker_privileged(docker, config, &Target::new_built_in(triple))
+            })
+        }
 
-impl MountFinder {
-    fn new(mounts: Vec<MountDetail>) -> MountFinder {
-        // sort by length (reverse), to give mounts with more path components a higher priority;
-        let mut mounts = mounts;
-        mounts.sort_by(|a, b| {
-            let la = a.destination.as_os_str().len();
-            let lb = b.destination.as_os_str().len();
-            la.cmp(&lb).reverse()
-        });
-        MountFinder { mounts }
+        #[test]
+        fn test_heuristic() -> Result<()> {
+            assert_eq!(args("", "armv7-linux-androideabi")?, ["--privileged"]);
+            assert!(args("", "i686-unknown-linux-gnu")?.is_empty());
+            Ok(())
+        }
+
+        #[test]
+        fn test_config_enables() -> Result<()> {
+            let toml = r#"
+                [target.i686-unknown-linux-gnu]
+                privileged = true
+            "#;
+            assert_eq!(args(toml, "i686-unknown-linux-gnu")?, ["--privileged"]);
+            Ok(())
+        }
+
+        #[test]
+        fn test_config_disables() -> Result<()> {
+            let toml = r#"
+                [target.armv7-linux-androideabi]
+                privileged = false
+            "#;
+            assert!(args(toml, "armv7-linux-androideabi")?.is_empty());
+            Ok(())
+        }
     }
 
-    fn find_mount_path(&self, path: impl AsRef<Path>) -> PathBuf {
-        let path = path.as_ref();
+    mod static_linking {
+        use super::*;
 
-        for info in &self.mounts {
-            if let Ok(stripped) = path.strip_prefix(&info.destination) {
-                return info.source.join(stripped);
+        #[test]
+        fn test_default_rustflags() {
+            assert_eq!(static_rustflags(None), "-C target-feature=+crt-static");
+        }
+
+        #[test]
+        fn test_append_to_user_rustflags() {
+            assert_eq!(
+                static_rustflags(Some("-C opt-level=3")),
+                "-C opt-level=3 -C target-feature=+crt-static"
+            );
+        }
+
+        #[test]
+        fn test_append_to_encoded_rustflags() {
+            assert_eq!(
+                static_encoded_rustflags("-C\x1fopt-level=3"),
+                "-C\x1fopt-level=3\x1f-C\x1ftarget-feature=+crt-static"
+            );
+            assert_eq!(
+                static_encoded_rustflags("-Ctarget-feature=+crt-static"),
+                "-Ctarget-feature=+crt-static"
+            );
+        }
+
+        #[test]
+        fn test_dedupe_user_rustflags() {
+            for rustflags in [
+                "-C target-feature=+crt-static",
+                "-Ctarget-feature=+crt-static -C opt-level=3",
+            ] {
+                assert_eq!(static_rustflags(Some(rustflags)), rustflags);
             }
         }
+    }
 
-        path.to_path_buf()
+    mod rustflags {
+        use super::*;
+        use std::ffi::OsStr;
+
+        fn forwarded(
+            triple: &str,
+            rustflags: Option<&str>,
+            encoded: Option<&str>,
+            toml: &str,
+        ) -> (Vec<String>, Vec<(String, String)>) {
+            let config = config_for(toml, &[]).unwrap();
+            let target = Target::new_built_in(triple);
+            let mut docker = Command::new(DOCKER);
+            docker_rustflags(&mut docker, &config, &target, rustflags, encoded).unwrap();
+            let args = docker
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect();
+            let envs = docker
+                .get_envs()
+                .map(|(k, v)| {
+                    let v = v.unwrap_or_else(|| OsStr::new(""));
+                    (
+                        k.to_string_lossy().into_owned(),
+                        v.to_string_lossy().into_owned(),
+                    )
+                })
+                .collect();
+            (args, envs)
+        }
+
+        #[test]
+        fn test_forward_host_rustflags() {
+            let (args, envs) = forwarded(MUSL, Some("-C opt-level=3"), None, "");
+            assert_eq!(args, ["-e", "RUSTFLAGS"]);
+            assert_eq!(envs, [("RUSTFLAGS".into(), "-C opt-level=3".into())]);
+        }
+
+        #[test]
+        fn test_musl_without_host_rustflags() {
+            // the rustflags of `.cargo/config.toml` must stay in effect
+            assert_eq!(forwarded(MUSL, None, None, ""), (vec![], vec![]));
+            // musl links statically already
+            assert_eq!(forwarded(MUSL, None, None, STATIC), (vec![], vec![]));
+        }
+
+        #[test]
+        fn test_static_without_host_rustflags() {
+            assert_eq!(forwarded(GNU, None, None, ""), (vec![], vec![]));
+
+            let (args, envs) = forwarded(GNU, None, None, STATIC);
+            assert_eq!(args, ["-e", "RUSTFLAGS"]);
+            assert_eq!(
+                envs,
+                [("RUSTFLAGS".into(), "-C target-feature=+crt-static".into())]
+            );
+        }
+
+        const MUSL: &str = "x86_64-unknown-linux-musl";
+        const GNU: &str = "x86_64-unknown-linux-gnu";
+        const STATIC: &str = r#"
+            [target.x86_64-unknown-linux-musl]
+            static = true
+
+            [target.x86_64-unknown-linux-gnu]
+            static = true
+        "#;
+
+        #[test]
+        fn test_encoded_rustflags_take_precedence() {
+            let (args, envs) =
+                forwarded(GNU, Some("-C opt-level=3"), Some("-Copt-level=1"), STATIC);
+            assert_eq!(args, ["-e", "CARGO_ENCODED_RUSTFLAGS"]);
+            assert_eq!(
+                envs,
+                [(
+                    "CARGO_ENCODED_RUSTFLAGS".into(),
+                    "-Copt-level=1\x1f-C\x1ftarget-feature=+crt-static".into()
+                )]
+            );
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    mod image_check {
+        use super::*;
 
-    mod mount_finder {
+        #[test]
+        fn test_parse_rustc_version() {
+            assert_eq!(
+                parse_rustc_version("rustc 1.62.0 (a8314ef7d 2022-06-27)\n"),
+                Some(Version::new(1, 62, 0))
+            );
+            assert!(parse_rustc_version("rustc 1.64.0-nightly (f8588549c 2022-07-18)").is_some());
+            assert_eq!(parse_rustc_version("sh: rustc: not found"), None);
+        }
+
+        #[test]
+        fn test_cached_rustc_version() {
+            let cache = "sha256:aaaa 1.62.0 1.58.1\nsha256:bbbb 1.62.0 -\n";
+            let toolchain = Version::new(1, 62, 0);
+            assert_eq!(
+                cached_rustc_version(cache, "sha256:aaaa", &toolchain),
+                Some(Some(Version::new(1, 58, 1)))
+            );
+            assert_eq!(
+                cached_rustc_version(cache, "sha256:bbbb", &toolchain),
+                Some(None)
+            );
+            assert_eq!(cached_rustc_version(cache, "sha256:cccc", &toolchain), None);
+            // found with another toolchain mounted
+            let toolchain = Version::new(1, 63, 0);
+            assert_eq!(cached_rustc_version(cache, "sha256:aaaa", &toolchain), None);
+        }
+
+        #[test]
+        fn test_removed_images_are_pruned() {
+            let cache = "sha256:aaaa 1.62.0 1.58.1\naaaa 1.62.0 -\nsha256:bbbb 1.62.0 -\n";
+            let local = "sha256:aaaa\nsha256:cccc\n";
+            assert_eq!(
+                retain_cache_entries(cache, |id| local_image(local, id)),
+                "sha256:aaaa 1.62.0 1.58.1\naaaa 1.62.0 -\n"
+            );
+        }
+
+        #[test]
+        fn test_mismatch_warning() {
+            let image = Version::new(1, 58, 1);
+            let warning = image_toolchain_mismatch("my/image", &image, &Version::new(1, 62, 0));
+            assert!(warning.unwrap().contains("Rust 1.58.1"));
+
+            assert_eq!(
+                image_toolchain_mismatch("my/image", &image, &Version::new(1, 58, 0)),
+                None
+            );
+        }
+    }
+
+    mod context {
         use super::*;
 
         #[test]
-        fn test_default_finder_returns_original() {
-            let finder = MountFinder::default();
+        fn test_socket_endpoint() {
             assert_eq!(
-                PathBuf::from("/test/path"),
-                finder.find_mount_path("/test/path"),
+                context_endpoint("unix:///Users/user/.colima/default/docker.sock\n").as_deref(),
+                Some("unix:///Users/user/.colima/default/docker.sock")
             );
+            assert_eq!(context_endpoint("tcp://10.0.0.1:2376"), None);
+            assert_eq!(context_endpoint(""), None);
+        }
+    }
+
+    mod server {
+        use super::*;
+
+        #[test]
+        fn test_key_follows_mounts_and_environment() {
+            let mut docker = Command::new(DOCKER);
+            docker.args(["-v", "/home/user/project:/project"]);
+            docker.args(["-e", "CROSS_TEST_SERVER_TOKEN"]);
+            docker.env("CROSS_TEST_SERVER_TOKEN", "first");
+            let key = server_key(&docker, "image");
+            assert_eq!(server_key(&docker, "image"), key);
+            assert_ne!(server_key(&docker, "other-image"), key);
+
+            docker.env("CROSS_TEST_SERVER_TOKEN", "second");
+            assert_ne!(server_key(&docker, "image"), key);
+
+            let mut moved = Command::new(DOCKER);
+            moved.args(["-v", "/home/user/moved:/project"]);
+            moved.args(["-e", "CROSS_TEST_SERVER_TOKEN"]);
+            moved.env("CROSS_TEST_SERVER_TOKEN", "first");
+            assert_ne!(server_key(&moved, "image"), key);
         }
 
         #[test]
-        fn test_longest_destination_path_wins() {
-            let finder = MountFinder::new(vec![
-                MountDetail {
-                    source: PathBuf::from("/project/path"),
-                    destination: PathBuf::from("/project"),
-                },
-                MountDetail {
-                    source: PathBuf::from("/target/path"),
-                    destination: PathBuf::from("/project/target"),
-                },
-            ]);
+        fn test_idle_timeout() {
+            assert!(server_script(Duration::from_secs(1800)).contains("-ge 1800 ]"));
+        }
+    }
+
+    mod seccomp {
+        use super::*;
+
+        fn seccomp_args(toml: &str, target: &str) -> Result<Vec<String>> {
+            args_for(toml, &[], |docker, config| {
+                docker_seccomp(docker, config, &Target::new_built_in(target))
+            })
+        }
+
+        #[test]
+        fn test_interpreted_target_is_unconfined_by_default() -> Result<()> {
             assert_eq!(
-                PathBuf::from("/target/path/test"),
-                finder.find_mount_path("/project/target/test")
-            )
+                seccomp_args("", "aarch64-unknown-linux-gnu")?,
+                ["--security-opt", "seccomp=unconfined"]
+            );
+            Ok(())
         }
 
         #[test]
-        fn test_adjust_multiple_paths() {
-            let finder = MountFinder::new(vec![
-                MountDetail {
-                    source: PathBuf::from("/var/lib/docker/overlay2/container-id/merged"),
-                    destination: PathBuf::from("/"),
-                },
-                MountDetail {
-                    source: PathBuf::from("/home/project/path"),
-                    destination: PathBuf::from("/project"),
-                },
-            ]);
+        fn test_native_target_keeps_default_profile() -> Result<()> {
+            assert!(seccomp_args("", "x86_64-unknown-linux-gnu")?.is_empty());
+            Ok(())
+        }
+
+        #[test]
+        fn test_interpreted_target_opt_out() -> Result<()> {
+            let toml = r#"
+                [target.aarch64-unknown-linux-gnu]
+                qemu-friendly = false
+            "#;
+            assert!(seccomp_args(toml, "aarch64-unknown-linux-gnu")?.is_empty());
+            Ok(())
+        }
+
+        #[test]
+        fn test_runner_overrides_heuristic() -> Result<()> {
+            let toml = r#"
+                [target.aarch64-unknown-linux-gnu]
+                runner = "native"
+
+                [target.x86_64-unknown-linux-gnu]
+                runner = "qemu-user"
+            "#;
+            assert!(seccomp_args(toml, "aarch64-unknown-linux-gnu")?.is_empty());
             assert_eq!(
-                PathBuf::from("/var/lib/docker/overlay2/container-id/merged/container/path"),
-                finder.find_mount_path("/container/path")
+                seccomp_args(toml, "x86_64-unknown-linux-gnu")?,
+                ["--security-opt", "seccomp=unconfined"]
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn test_configured_profile() -> Result<()> {
+            let toml = r#"
+                [build]
+                seccomp = "/etc/cross/seccomp.json"
+            "#;
+            for target in ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu"] {
+                assert_eq!(
+                    seccomp_args(toml, target)?,
+                    ["--security-opt", "seccomp=/etc/cross/seccomp.json"]
+                );
+            }
+
+            let unconfined = "[build]\nseccomp = \"unconfined\"";
+            assert_eq!(
+                seccomp_args(unconfined, "x86_64-unknown-linux-gnu")?,
+                ["--security-opt", "seccomp=unconfined"]
             );
+            Ok(())
+        }
+    }
+
+    mod wsl {
+        use super::*;
+
+        #[test]
+        fn test_windows_drive_path() {
             assert_eq!(
-                PathBuf::from("/home/project/path"),
-                finder.find_mount_path("/project")
+                wsl_drive_path(Path::new("/mnt/c/Users/user/project")),
+                Some(PathBuf::from("C:/Users/user/project"))
             );
             assert_eq!(
-                PathBuf::from("/home/project/path/target"),
-                finder.find_mount_path("/project/target")
+                wsl_drive_path(Path::new("/mnt/d")),
+                Some(PathBuf::from("D:/"))
+            );
+        }
+
+        #[test]
+        fn test_wsl_filesystem_path() {
+            assert_eq!(wsl_drive_path(Path::new("/home/user/project")), None);
+            assert_eq!(wsl_drive_path(Path::new("/mnt/wsl/project")), None);
+        }
+    }
+
+    mod image_digest {
+        use super::*;
+
+        #[test]
+        fn test_parse_imagetools_digest() {
+            let output = "Name:      ghcr.io/cross-rs/aarch64-unknown-linux-gnu:main\n\
+                          MediaType: application/vnd.oci.image.index.v1+json\n\
+                          Digest:    sha256:0123456789abcdef\n\
+                          \n\
+                          Manifests:\n  \
+                          Name:      ghcr.io/cross-rs/aarch64-unknown-linux-gnu:main@sha256:fedcba\n";
+            assert_eq!(
+                parse_imagetools_digest(output).as_deref(),
+                Some("sha256:0123456789abcdef")
+            );
+            assert_eq!(parse_imagetools_digest("ERROR: not found\n"), None);
+        }
+
+        #[test]
+        fn test_parse_repo_digest() {
+            assert_eq!(
+                parse_repo_digest(
+                    "[\"ghcr.io/cross-rs/aarch64-unknown-linux-gnu@sha256:0123456789abcdef\"]\n"
+                )
+                .as_deref(),
+                Some("sha256:0123456789abcdef")
             );
+            assert_eq!(parse_repo_digest("[]"), None);
+            assert_eq!(parse_repo_digest("null"), None);
         }
     }
 
@@ -0,0 +1,115 @@
+//! `.env` files, of `KEY=VALUE` lines, passed into the container
+
+use std::path::Path;
+
+use crate::errors::*;
+use crate::file;
+use eyre::bail;
+
+/// Reads the configured env file, relative to the project `root`, or the
+/// `.env` there if it exists
+pub fn load(root: &Path, configured: Option<String>) -> Result<Vec<(String, String)>> {
+    match configured {
+        Some(path) => read(&root.join(path)),
+        None if root.join(".env").exists() => read(&root.join(".env")),
+        None => Ok(vec![]),
+    }
+}
+
+/// Reads the variables of the `.env` file at `path`
+fn read(path: &Path) -> Result<Vec<(String, String)>> {
+    let content = file::read(path)?;
+    parse(&content).wrap_err_with(|| format!("invalid env file `{}`", path.display()))
+}
+
+/// Parses `KEY=VALUE` lines, skipping empty lines and `#` comments. Values
+/// may be double-quoted, with `\n`, `\"` and `\\` escapes, or single-quoted,
+/// taken literally; unquoted values end at a ` #` comment.
+pub fn parse(content: &str) -> Result<Vec<(String, String)>> {
+    let mut vars = vec![];
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => bail!("expected `KEY=VALUE` on line {}", index + 1),
+        };
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            bail!("invalid variable name `{key}` on line {}", index + 1);
+        }
+
+        let value = parse_value(value)
+            .ok_or_else(|| eyre::eyre!("unterminated quote on line {}", index + 1))?;
+        vars.push((key.to_string(), value));
+    }
+
+    Ok(vars)
+}
+
+fn parse_value(value: &str) -> Option<String> {
+    if let Some(quoted) = value.strip_prefix('"') {
+        let mut parsed = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Some(parsed),
+                '\\' => match chars.next()? {
+                    'n' => parsed.push('\n'),
+                    c => parsed.push(c),
+                },
+                c => parsed.push(c),
+            }
+        }
+        None
+    } else if let Some(quoted) = value.strip_prefix('\'') {
+        quoted
+            .split_once('\'')
+            .map(|(literal, _)| literal.to_string())
+    } else {
+        let value = match value.find(" #") {
+            Some(comment) => &value[..comment],
+            None => value,
+        };
+        Some(value.trim_end().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_lines() -> Result<()> {
+        let vars = parse(
+            r#"
+            # build configuration
+            PROFILE=release # inline comment
+            export TOKEN="a \"quoted\" secret"
+            GREETING='hello # world'
+            EMPTY=
+            "#,
+        )?;
+        assert_eq!(
+            vars,
+            [
+                ("PROFILE".to_string(), "release".to_string()),
+                ("TOKEN".to_string(), "a \"quoted\" secret".to_string()),
+                ("GREETING".to_string(), "hello # world".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("NO_VALUE").is_err());
+        assert!(parse("BAD KEY=1").is_err());
+        assert!(parse("OPEN=\"unterminated").is_err());
+    }
+}
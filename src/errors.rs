@@ -1,9 +1,133 @@
+use std::{env, fmt};
+
+use color_eyre::config::{HookBuilder, Theme};
+use color_eyre::owo_colors::Style;
 pub use color_eyre::Section;
 pub use eyre::Context;
 pub use eyre::Result;
 
-pub fn install_panic_hook() -> Result<()> {
-    color_eyre::config::HookBuilder::new()
+pub fn install_panic_hook(color: bool, verbose: bool) -> Result<()> {
+    // backtraces are only captured when asked for through the environment
+    if verbose && env::var_os("RUST_LIB_BACKTRACE").is_none() {
+        env::set_var("RUST_LIB_BACKTRACE", "1");
+    }
+
+    let theme = if color { Theme::dark() } else { Theme::new() };
+    HookBuilder::new()
         .display_env_section(false)
+        // the chain of errors is rendered by `Report`
+        .theme(theme.error(Style::new()))
         .install()
 }
+
+/// An error report, with each cause indented under the error it caused and
+/// the root cause highlighted, followed by the sections of the report
+/// (suggestions, warnings, and the backtrace when `--verbose`)
+pub struct Report<'a> {
+    error: &'a eyre::Report,
+    color: bool,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(error: &'a eyre::Report, color: bool) -> Self {
+        Report { error, color }
+    }
+
+    fn style(&self, style: Style) -> Style {
+        if self.color {
+            style
+        } else {
+            Style::new()
+        }
+    }
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let root = self.error.chain().count() - 1;
+        for (depth, error) in self.error.chain().enumerate() {
+            let prefix = if depth == 0 {
+                "Error: ".to_string()
+            } else {
+                format!("\n{}└─ ", "   ".repeat(depth - 1))
+            };
+            let indent = " ".repeat(prefix.trim_start_matches('\n').chars().count());
+            let message = error.to_string().replace('\n', &format!("\n{indent}"));
+
+            if depth == 0 {
+                write!(f, "{}", self.style(Style::new().red().bold()).style(prefix))?;
+            } else {
+                write!(f, "{}", self.style(Style::new().dimmed()).style(prefix))?;
+            }
+            if depth == root && root > 0 {
+                write!(
+                    f,
+                    "{}",
+                    self.style(Style::new().red().bold()).style(message)
+                )?;
+            } else {
+                write!(f, "{message}")?;
+            }
+        }
+
+        let sections = format!("{:?}", Sections(self.error));
+        let sections = sections.trim();
+        if !sections.is_empty() {
+            write!(f, "\n\n{sections}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The sections of a report, rendered by its handler for an error without
+/// a message
+struct Sections<'a>(&'a eyre::Report);
+
+impl fmt::Debug for Sections<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.handler().debug(&Unnamed, f)
+    }
+}
+
+#[derive(Debug)]
+struct Unnamed;
+
+impl fmt::Display for Unnamed {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl std::error::Error for Unnamed {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error() -> eyre::Report {
+        eyre::eyre!("network is unreachable")
+            .wrap_err("`rustup` failed")
+            .wrap_err("couldn't install toolchain `nightly`")
+    }
+
+    #[test]
+    fn indented_chain() {
+        let report = Report::new(&error(), false).to_string();
+        assert!(report.starts_with(
+            "Error: couldn't install toolchain `nightly`\n\
+             └─ `rustup` failed\n   \
+             └─ network is unreachable"
+        ));
+    }
+
+    #[test]
+    fn highlighted_root_cause() {
+        let report = Report::new(&error(), true).to_string();
+        assert!(report.contains(&format!(
+            "{}",
+            Style::new().red().bold().style("network is unreachable")
+        )));
+        assert!(!Report::new(&error(), false).to_string().contains('\x1b'));
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+use std::io::Write;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Lifecycle events of `cross` itself, emitted as JSON lines on stderr when
+/// `--message-format=json` is passed.
+///
+/// Messages produced by `cargo` inside the container are untouched and keep
+/// flowing to stdout, so these never interleave with them.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    ImageResolved { target: &'a str, image: &'a str },
+    ToolchainInstalled { toolchain: &'a str },
+    ContainerStarted { target: &'a str, image: &'a str },
+    ContainerExited { target: &'a str, code: Option<i32> },
+    CrossError { message: String },
+}
+
+impl Event<'_> {
+    /// Writes the event to stderr if `enabled`
+    pub fn emit(&self, enabled: bool) {
+        if enabled {
+            let stderr = std::io::stderr();
+            let mut stderr = stderr.lock();
+            // failing to report progress must never fail the build
+            if let Ok(line) = serde_json::to_string(self) {
+                writeln!(stderr, "{line}").ok();
+            }
+        }
+    }
+}
+
+/// The summary of a build in a container, printed on a single line to stderr
+/// when `--timings` or `CROSS_TIMINGS` is given, for CI dashboards to grep
+#[derive(Debug)]
+pub struct Summary<'a> {
+    pub target: &'a str,
+    pub image: &'a str,
+    /// The toolchain on the host, if managed by rustup
+    pub toolchain: Option<&'a str>,
+    /// The wall-clock time spent in the container
+    pub elapsed: Duration,
+    pub code: Option<i32>,
+}
+
+impl Summary<'_> {
+    /// Writes the summary to stderr if `enabled`
+    pub fn emit(&self, enabled: bool) {
+        let stderr = std::io::stderr();
+        self.write(&mut stderr.lock(), enabled);
+    }
+
+    fn write(&self, out: &mut impl Write, enabled: bool) {
+        if enabled {
+            writeln!(out, "{self}").ok();
+        }
+    }
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cross-summary: target={} image={} toolchain={} time={:.2}s status=",
+            self.target,
+            self.image,
+            self.toolchain.unwrap_or("system"),
+            self.elapsed.as_secs_f64(),
+        )?;
+        match self.code {
+            Some(code) => write!(f, "{code}"),
+            None => f.write_str("signal"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_with_reason_tag() {
+        let event = Event::ImageResolved {
+            target: "aarch64-unknown-linux-gnu",
+            image: "ghcr.io/cross-rs/aarch64-unknown-linux-gnu:main",
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"reason":"image-resolved","target":"aarch64-unknown-linux-gnu","image":"ghcr.io/cross-rs/aarch64-unknown-linux-gnu:main"}"#
+        );
+    }
+
+    fn summary() -> Summary<'static> {
+        Summary {
+            target: "aarch64-unknown-linux-gnu",
+            image: "ghcr.io/cross-rs/aarch64-unknown-linux-gnu:main",
+            toolchain: Some("stable-x86_64-unknown-linux-gnu"),
+            elapsed: Duration::from_millis(12_345),
+            code: Some(0),
+        }
+    }
+
+    #[test]
+    fn summary_with_timings() {
+        let mut out = vec![];
+        summary().write(&mut out, true);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "cross-summary: target=aarch64-unknown-linux-gnu \
+             image=ghcr.io/cross-rs/aarch64-unknown-linux-gnu:main \
+             toolchain=stable-x86_64-unknown-linux-gnu time=12.35s status=0\n"
+        );
+    }
+
+    #[test]
+    fn summary_without_timings() {
+        let mut out = vec![];
+        summary().write(&mut out, false);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn serialize_exit_without_code() {
+        let event = Event::ContainerExited {
+            target: "aarch64-unknown-linux-gnu",
+            code: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"reason":"container-exited","target":"aarch64-unknown-linux-gnu","code":null}"#
+        );
+    }
+}
@@ -53,6 +53,21 @@ impl CommandExt for Command {
     }
 }
 
+/// The exit code of a process, as a shell reports it: a process killed by a
+/// signal exits with 128 plus the signal number, which is also what the
+/// container engine exits with when the process in the container is killed
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+
+    status.code().unwrap_or(1)
+}
+
 pub struct SafeCommand {
     program: String,
     args: Vec<String>,
@@ -105,3 +120,24 @@ impl From<SafeCommand> for Command {
         cmd
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn exit_code_of_killed_process() -> Result<()> {
+        // as the container engine reports a process killed in the container
+        let status = Command::new("sh").args(["-c", "exit 137"]).status()?;
+        assert_eq!(exit_code(status), 137);
+
+        let status = Command::new("sh").args(["-c", "kill -KILL $$"]).status()?;
+        assert_eq!(status.code(), None);
+        assert_eq!(exit_code(status), 137);
+
+        let status = Command::new("sh").args(["-c", "exit 3"]).status()?;
+        assert_eq!(exit_code(status), 3);
+        Ok(())
+    }
+}
@@ -1,27 +1,226 @@
-use std::path::Path;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use crate::errors::*;
 use crate::file;
-use crate::Target;
+use crate::{docker, Target};
 
-/// Checks if the interpreters have been registered in the host system
+/// Checks if the interpreters have been registered in the host system, and
+/// that the registration still works
 pub fn is_registered(target: &Target) -> Result<bool> {
     if file::read("/proc/sys/fs/binfmt_misc/status")?.trim() != "enabled" {
         eyre::bail!("host system doesn't have binfmt_misc support")
     }
 
-    let ok = if target.is_windows() {
-        let wine = Path::new("/proc/sys/fs/binfmt_misc/wine");
-        wine.exists() && {
-            let f = file::read(wine)?;
+    let entry = entry_path(target);
+    let ok = entry.exists() && {
+        let f = file::read(entry)?;
+        let interpreter = if target.is_windows() {
             f.contains("/usr/bin/run-detectors")
                 || f.contains("/usr/lib/binfmt-support/run-detectors")
-        }
-    } else {
-        // NOTE checking any architecture will do, here we pick arm
-        let qemu = Path::new("/proc/sys/fs/binfmt_misc/qemu-arm");
-        qemu.exists() && file::read(qemu)?.contains("/usr/bin/qemu-arm-static")
+        } else {
+            f.contains("/usr/bin/qemu-arm-static")
+        };
+        interpreter && is_registration_valid(&f)
     };
 
     Ok(ok)
 }
+
+/// The binfmt_misc entry of the interpreter of `target`
+fn entry_path(target: &Target) -> &'static Path {
+    if target.is_windows() {
+        Path::new("/proc/sys/fs/binfmt_misc/wine")
+    } else {
+        // NOTE checking any architecture will do, here we pick arm
+        Path::new("/proc/sys/fs/binfmt_misc/qemu-arm")
+    }
+}
+
+/// Whether a binfmt_misc entry can still run binaries: it's enabled, and its
+/// interpreter exists. After a host update, the entry can point at a deleted
+/// interpreter, and running fails with "exec format error". An interpreter
+/// loaded at registration (the `F` flag) doesn't need to exist anymore.
+fn is_registration_valid(entry: &str) -> bool {
+    let mut lines = entry.lines();
+    if lines.next().map(str::trim) != Some("enabled") {
+        return false;
+    }
+
+    let mut interpreter = None;
+    let mut fix_binary = false;
+    for line in lines {
+        if let Some(path) = line.strip_prefix("interpreter ") {
+            interpreter = Some(Path::new(path.trim()));
+        } else if let Some(flags) = line.strip_prefix("flags:") {
+            fix_binary = flags.contains('F');
+        }
+    }
+
+    fix_binary || interpreter.map_or(false, Path::exists)
+}
+
+/// Whether `target` needs an interpreter registered on the host. A `runner` of
+/// `qemu` (or `qemu-user`) forces it, and `native` or `none` skip it, for the
+/// targets the heuristic over the triple gets wrong.
+pub fn is_needed(target: &Target, runner: Option<&str>) -> bool {
+    match runner {
+        Some("qemu" | "qemu-user") => true,
+        Some("native" | "none") => false,
+        _ => target.needs_interpreter(),
+    }
+}
+
+/// The handlers registered in binfmt_misc, like `qemu-arm` or `wine`
+pub fn handlers() -> BTreeSet<String> {
+    fs::read_dir("/proc/sys/fs/binfmt_misc")
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| name != "register" && name != "status")
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Registers the interpreter for `target` unless it already is, and
+/// re-registers the QEMU interpreters of all targets when `qemu` asks for
+/// another version than the one `cross` registered last. Returns whether
+/// anything was registered.
+pub fn ensure_registered(target: &Target, qemu: Option<&str>, verbose: bool) -> Result<bool> {
+    let registered = is_registered(target)?;
+    let qemu = qemu.filter(|_| !target.is_windows());
+    let recorded = qemu.and_then(|_| recorded_version());
+    match action(registered, recorded.as_deref(), qemu) {
+        Action::None => return Ok(false),
+        // a stale QEMU entry is in the way of registering again
+        Action::Register if !target.is_windows() && entry_path(target).exists() => {
+            docker::unregister(verbose)?;
+            docker::register(target, qemu, verbose)?;
+        }
+        Action::Register => docker::register(target, qemu, verbose)?,
+        Action::Reregister => {
+            docker::unregister(verbose)?;
+            docker::register(target, qemu, verbose)?;
+        }
+    }
+    record_version(qemu);
+
+    Ok(true)
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    None,
+    Register,
+    Reregister,
+}
+
+fn action(registered: bool, recorded: Option<&str>, requested: Option<&str>) -> Action {
+    match requested {
+        _ if !registered => Action::Register,
+        Some(requested) if recorded != Some(requested) => Action::Reregister,
+        _ => Action::None,
+    }
+}
+
+/// binfmt_misc doesn't know which QEMU provides an interpreter, so the version
+/// `cross` registered is kept in `~/.cache/cross/qemu-version`
+fn version_file() -> Option<PathBuf> {
+    home::home_dir().map(|home| home.join(".cache/cross/qemu-version"))
+}
+
+fn recorded_version() -> Option<String> {
+    let version = fs::read_to_string(version_file()?).ok()?;
+    Some(version.trim().to_string())
+}
+
+/// Records the registered version, or forgets it when the distribution's
+/// QEMU was registered instead
+fn record_version(version: Option<&str>) {
+    if let Some(file) = version_file() {
+        match version {
+            Some(version) => {
+                fs::create_dir_all(file.parent().unwrap()).ok();
+                fs::write(file, version).ok();
+            }
+            None => {
+                fs::remove_file(file).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(state: &str, interpreter: &Path, flags: &str) -> String {
+        format!(
+            "{state}\ninterpreter {}\nflags: {flags}\noffset 0\n\
+             magic 7f454c460101010000000000000000000200\n",
+            interpreter.display()
+        )
+    }
+
+    #[test]
+    fn stale_registration() {
+        let dir = std::env::temp_dir().join(format!("cross-binfmt-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let qemu = dir.join("qemu-arm-static");
+        fs::write(&qemu, "").unwrap();
+        let missing = dir.join("missing-qemu-arm-static");
+
+        assert!(is_registration_valid(&entry("enabled", &qemu, "OC")));
+        assert!(!is_registration_valid(&entry("enabled", &missing, "OC")));
+        assert!(!is_registration_valid(&entry("disabled", &qemu, "OC")));
+        // the kernel keeps the interpreter open
+        assert!(is_registration_valid(&entry("enabled", &missing, "OCF")));
+
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn runner_overrides_heuristic() {
+        let linux = Target::new_custom("mycpu-unknown-linux-gnu");
+        let none = Target::new_custom("mycpu-unknown-none");
+        assert!(is_needed(&linux, None));
+        assert!(!is_needed(&none, None));
+
+        assert!(is_needed(&none, Some("qemu")));
+        assert!(is_needed(&none, Some("qemu-user")));
+        assert!(!is_needed(&linux, Some("native")));
+        assert!(!is_needed(&linux, Some("none")));
+        // other runners don't run the binaries under binfmt_misc
+        assert!(is_needed(&linux, Some("qemu-system")));
+        assert!(!is_needed(&none, Some("custom-runner")));
+    }
+
+    #[test]
+    fn register_when_missing() {
+        assert_eq!(action(false, None, None), Action::Register);
+        assert_eq!(
+            action(false, Some("7.2.0-1"), Some("7.2.0-1")),
+            Action::Register
+        );
+    }
+
+    #[test]
+    fn reregister_on_version_change() {
+        assert_eq!(
+            action(true, Some("6.1.0-8"), Some("7.2.0-1")),
+            Action::Reregister
+        );
+        // registered by something else than `cross`
+        assert_eq!(action(true, None, Some("7.2.0-1")), Action::Reregister);
+    }
+
+    #[test]
+    fn keep_registered() {
+        assert_eq!(action(true, None, None), Action::None);
+        assert_eq!(action(true, Some("7.2.0-1"), None), Action::None);
+        assert_eq!(action(true, Some("7.2.0-1"), Some("7.2.0-1")), Action::None);
+    }
+}
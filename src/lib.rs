@@ -0,0 +1,1219 @@
+//! `cross` runs cargo for a target in a container with the toolchain and
+//! system libraries it needs. Besides the `cross` binary, the build can be run
+//! from other programs with [`CrossBuilder`].
+
+#![deny(missing_debug_implementations, rust_2018_idioms)]
+
+#[cfg(test)]
+mod tests;
+
+mod build_info;
+mod builder;
+mod cargo;
+mod cli;
+mod config;
+mod cross_lock;
+mod cross_toml;
+mod docker;
+mod env_file;
+mod errors;
+mod events;
+mod extensions;
+mod file;
+mod id;
+mod interpreter;
+mod rustc;
+mod rustup;
+
+use std::env;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::Once;
+use std::time::Instant;
+
+use serde::Deserialize;
+
+use self::build_info::BuildInfo;
+use self::cargo::{Root, Subcommand};
+use self::cross_lock::CrossLock;
+use self::cross_toml::{CrossToml, Issue};
+use self::errors::*;
+use self::extensions::CommandExt;
+
+pub use self::builder::{CrossBuilder, CrossOutcome};
+pub use self::config::Config;
+use self::events::{Event, Summary};
+use self::rustc::{TargetList, VersionMetaExt};
+pub use eyre::Result;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Host {
+    Other(String),
+
+    // OSX
+    X86_64AppleDarwin,
+    // Support Apple Silicon, as developers are starting to use it as development workstation.
+    Aarch64AppleDarwin,
+
+    // Linux
+    X86_64UnknownLinuxGnu,
+    // Linux Aarch64 is become more popular in CI pipelines (e.g. to AWS Graviton based systems)
+    Aarch64UnknownLinuxGnu,
+    // (Alpine) Linux (musl) often use in CI pipelines to cross compile rust projects to different
+    // targets (e.g. in GitLab CI pipelines).
+    X86_64UnknownLinuxMusl,
+    // (Alpine) Linux (musl) often use in CI pipelines to cross compile rust projects to different
+    // targets (e.g. in GitLab CI pipelines). Now, that AWS Graviton based systems are gaining
+    // attraction CI pipelines might run on (Alpine) Linux Aarch64.
+    Aarch64UnknownLinuxMusl,
+
+    // FreeBSD, which can drive Docker and Podman through Linux emulation
+    X86_64UnknownFreebsd,
+
+    // Windows MSVC
+    X86_64PcWindowsMsvc,
+    // Windows on ARM development machines
+    Aarch64PcWindowsMsvc,
+}
+
+impl Host {
+    /// Checks if this `(host, target)` pair is supported by `cross`
+    ///
+    /// `target == None` means `target == host`
+    fn is_supported(&self, target: Option<&Target>) -> bool {
+        match std::env::var("CROSS_COMPATIBILITY_VERSION")
+            .as_ref()
+            .map(|v| v.as_str())
+        {
+            // Old behavior (up to cross version 0.2.1) can be activated on demand using environment
+            // variable `CROSS_COMPATIBILITY_VERSION`.
+            Ok("0.2.1") => match self {
+                Host::X86_64AppleDarwin | Host::Aarch64AppleDarwin => {
+                    target.map(|t| t.needs_docker()).unwrap_or(false)
+                }
+                Host::X86_64UnknownLinuxGnu
+                | Host::Aarch64UnknownLinuxGnu
+                | Host::X86_64UnknownLinuxMusl
+                | Host::Aarch64UnknownLinuxMusl
+                | Host::X86_64UnknownFreebsd => target.map(|t| t.needs_docker()).unwrap_or(true),
+                Host::X86_64PcWindowsMsvc | Host::Aarch64PcWindowsMsvc => target
+                    .map(|t| t.triple() != self.triple() && t.needs_docker())
+                    .unwrap_or(false),
+                Host::Other(_) => false,
+            },
+            // New behaviour, if a target is provided (--target ...) then always run with docker
+            // image unless the target explicitly opts-out (i.e. unless needs_docker() returns false).
+            // If no target is provided run natively (on host) using cargo.
+            //
+            // This not only simplifies the logic, it also enables forward-compatibility without
+            // having to change cross every time someone comes up with the need for a new host/target
+            // combination. It's totally fine to call cross with `--target=$host_triple`, for
+            // example to test custom docker images. Cross should not try to recognize if host and
+            // target are equal, it's a user decision and if user want's to bypass cross he can call
+            // cargo directly or omit the `--target` option.
+            _ => target.map(|t| t.needs_docker()).unwrap_or(false),
+        }
+    }
+
+    /// Whether this is an `x86_64` macOS host on Apple Silicon, translated by
+    /// Rosetta, given the output of `sysctl -n hw.optional.arm64`
+    fn is_translated(&self, arm64_hardware: Option<&str>) -> bool {
+        *self == Host::X86_64AppleDarwin && arm64_hardware.map_or(false, |v| v.trim() == "1")
+    }
+
+    /// Why `cargo` is run on the host for `target`, unless `cross` supports
+    /// it or it's the host itself
+    fn fallback(&self, target: &Target) -> Option<HostFallback> {
+        if self.is_supported(Some(target)) || target.triple() == self.triple() {
+            None
+        } else if std::env::var("CROSS_COMPATIBILITY_VERSION").as_deref() == Ok("0.2.1") {
+            Some(HostFallback::Unsupported)
+        } else {
+            Some(HostFallback::NoContainer)
+        }
+    }
+
+    /// Returns the [`Target`] as target triple string
+    fn triple(&self) -> &str {
+        match self {
+            Host::X86_64AppleDarwin => "x86_64-apple-darwin",
+            Host::Aarch64AppleDarwin => "aarch64-apple-darwin",
+            Host::X86_64UnknownLinuxGnu => "x86_64-unknown-linux-gnu",
+            Host::Aarch64UnknownLinuxGnu => "aarch64-unknown-linux-gnu",
+            Host::X86_64UnknownLinuxMusl => "x86_64-unknown-linux-musl",
+            Host::Aarch64UnknownLinuxMusl => "aarch64-unknown-linux-musl",
+            Host::X86_64UnknownFreebsd => "x86_64-unknown-freebsd",
+            Host::X86_64PcWindowsMsvc => "x86_64-pc-windows-msvc",
+            Host::Aarch64PcWindowsMsvc => "aarch64-pc-windows-msvc",
+            Host::Other(s) => s.as_str(),
+        }
+    }
+}
+
+impl From<&str> for Host {
+    fn from(s: &str) -> Host {
+        match s {
+            "x86_64-apple-darwin" => Host::X86_64AppleDarwin,
+            "x86_64-unknown-linux-gnu" => Host::X86_64UnknownLinuxGnu,
+            "x86_64-unknown-linux-musl" => Host::X86_64UnknownLinuxMusl,
+            "x86_64-unknown-freebsd" => Host::X86_64UnknownFreebsd,
+            "x86_64-pc-windows-msvc" => Host::X86_64PcWindowsMsvc,
+            "aarch64-apple-darwin" => Host::Aarch64AppleDarwin,
+            "aarch64-unknown-linux-gnu" => Host::Aarch64UnknownLinuxGnu,
+            "aarch64-unknown-linux-musl" => Host::Aarch64UnknownLinuxMusl,
+            "aarch64-pc-windows-msvc" => Host::Aarch64PcWindowsMsvc,
+            s => Host::Other(s.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(from = "&str")]
+pub enum Target {
+    BuiltIn { triple: String },
+    Custom { triple: String },
+}
+
+impl Target {
+    fn new_built_in(triple: &str) -> Self {
+        Target::BuiltIn {
+            triple: triple.to_owned(),
+        }
+    }
+
+    fn new_custom(triple: &str) -> Self {
+        Target::Custom {
+            triple: triple.to_owned(),
+        }
+    }
+
+    fn triple(&self) -> &str {
+        match *self {
+            Target::BuiltIn { ref triple } => triple,
+            Target::Custom { ref triple } => triple,
+        }
+    }
+
+    fn is_apple(&self) -> bool {
+        self.triple().contains("apple")
+    }
+
+    fn is_bare_metal(&self) -> bool {
+        self.triple().contains("thumb")
+    }
+
+    fn is_builtin(&self) -> bool {
+        match *self {
+            Target::BuiltIn { .. } => true,
+            Target::Custom { .. } => false,
+        }
+    }
+
+    fn is_bsd(&self) -> bool {
+        self.triple().contains("bsd") || self.triple().contains("dragonfly")
+    }
+
+    fn is_solaris(&self) -> bool {
+        self.triple().contains("solaris")
+    }
+
+    fn is_android(&self) -> bool {
+        self.triple().contains("android")
+    }
+
+    fn is_emscripten(&self) -> bool {
+        self.triple().contains("emscripten")
+    }
+
+    fn is_musl(&self) -> bool {
+        self.triple().contains("musl")
+    }
+
+    fn is_linux(&self) -> bool {
+        self.triple().contains("linux") && !self.is_android()
+    }
+
+    fn is_windows(&self) -> bool {
+        self.triple().contains("windows")
+    }
+
+    fn needs_docker(&self) -> bool {
+        self.is_linux()
+            || self.is_android()
+            || self.is_bare_metal()
+            || self.is_bsd()
+            || self.is_solaris()
+            || !self.is_builtin()
+            || self.is_windows()
+            || self.is_emscripten()
+            || self.is_apple()
+    }
+
+    fn needs_interpreter(&self) -> bool {
+        let native = self.triple().starts_with("x86_64")
+            || self.triple().starts_with("i586")
+            || self.triple().starts_with("i686");
+
+        !native && (self.is_linux() || self.is_windows() || self.is_bare_metal())
+    }
+
+    fn needs_docker_privileged(&self) -> bool {
+        let arch_32bit = self.triple().starts_with("arm")
+            || self.triple().starts_with("i586")
+            || self.triple().starts_with("i686");
+
+        arch_32bit && self.is_android()
+    }
+
+    /// A target specification in `dirs` named after this built-in target.
+    /// `rustc` never reads it, since built-in targets take precedence over
+    /// the files found in the current directory or `RUST_TARGET_PATH`.
+    fn shadowed_spec(&self, dirs: &[PathBuf]) -> Option<PathBuf> {
+        if !self.is_builtin() {
+            return None;
+        }
+
+        dirs.iter()
+            .map(|dir| dir.join(format!("{}.json", self.triple())))
+            .find(|spec| spec.is_file())
+    }
+
+    /// The Debian architecture of packages for the target, for `cargo deb`
+    fn debian_arch(&self) -> Option<&'static str> {
+        if !self.is_linux() {
+            return None;
+        }
+
+        let triple = self.triple();
+        let arch = triple.split('-').next().unwrap_or_default();
+        let arch = match arch {
+            "x86_64" => "amd64",
+            "i586" | "i686" => "i386",
+            "aarch64" => "arm64",
+            "arm" | "armv5te" | "armv7" | "thumbv7neon" if triple.ends_with("hf") => "armhf",
+            "arm" | "armv5te" | "armv7" | "thumbv7neon" => "armel",
+            "mips" => "mips",
+            "mipsel" => "mipsel",
+            "mips64" => "mips64",
+            "mips64el" => "mips64el",
+            "powerpc" => "powerpc",
+            "powerpc64" => "ppc64",
+            "powerpc64le" => "ppc64el",
+            "riscv64gc" => "riscv64",
+            "s390x" => "s390x",
+            "sparc64" => "sparc64",
+            _ => return None,
+        };
+        Some(arch)
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.triple())
+    }
+}
+
+impl Target {
+    fn from(triple: &str, target_list: &TargetList) -> Target {
+        if target_list.contains(triple) {
+            Target::new_built_in(triple)
+        } else {
+            Target::new_custom(triple)
+        }
+    }
+}
+
+impl From<Host> for Target {
+    fn from(host: Host) -> Target {
+        match host {
+            Host::X86_64UnknownLinuxGnu => Target::new_built_in("x86_64-unknown-linux-gnu"),
+            Host::X86_64UnknownLinuxMusl => Target::new_built_in("x86_64-unknown-linux-musl"),
+            Host::X86_64AppleDarwin => Target::new_built_in("x86_64-apple-darwin"),
+            Host::X86_64UnknownFreebsd => Target::new_built_in("x86_64-unknown-freebsd"),
+            Host::X86_64PcWindowsMsvc => Target::new_built_in("x86_64-pc-windows-msvc"),
+            Host::Aarch64AppleDarwin => Target::new_built_in("aarch64-apple-darwin"),
+            Host::Aarch64UnknownLinuxGnu => Target::new_built_in("aarch64-unknown-linux-gnu"),
+            Host::Aarch64UnknownLinuxMusl => Target::new_built_in("aarch64-unknown-linux-musl"),
+            Host::Aarch64PcWindowsMsvc => Target::new_built_in("aarch64-pc-windows-msvc"),
+            Host::Other(s) => Target::from(s.as_str(), &rustc::target_list(false).unwrap()),
+        }
+    }
+}
+
+impl From<&str> for Target {
+    fn from(target_str: &str) -> Target {
+        let target_host: Host = target_str.into();
+        target_host.into()
+    }
+}
+
+/// Runs `cross` with the command line arguments, which is what the `cross`
+/// binary does, returning the code to exit with: that of a failed build, or 1
+/// once an error has been reported
+pub fn main() -> Result<i32> {
+    let target_list = rustc::target_list(false)?;
+    let args = cli::parse(&target_list);
+    let color = match args.color.as_deref() {
+        Some("always") => true,
+        Some("never") => false,
+        _ => atty::is(atty::Stream::Stderr),
+    };
+    install_panic_hook(color, args.verbose)?;
+    let json_messages = args.json_messages;
+
+    let builds = match expand_all_targets(args, &target_list).and_then(|args| {
+        let cd = env::current_dir().wrap_err("couldn't get current directory")?;
+        cargo_config_targets(args, &target_list, &cd)
+    }) {
+        Ok(args) => args.per_target(),
+        Err(err) => {
+            Event::CrossError {
+                message: format!("{err:#}"),
+            }
+            .emit(json_messages);
+            eprintln!("{}", Report::new(&err, color));
+            return Ok(1);
+        }
+    };
+    let multiple = builds.len() > 1;
+    let mut failed = None;
+    for args in builds {
+        if let Some(target) = args.target.as_ref().filter(|_| multiple) {
+            eprintln!("==> {target}");
+        }
+        match run(args, &target_list) {
+            Ok(Some(status)) if !status.success() => failed = failed.or(Some(status)),
+            Ok(_) => {}
+            Err(err) => {
+                Event::CrossError {
+                    message: format!("{err:#}"),
+                }
+                .emit(json_messages);
+                eprintln!("{}", Report::new(&err, color));
+                return Ok(1);
+            }
+        }
+    }
+
+    // the builds of all the targets are run, but any failure fails `cross`
+    Ok(failed.map_or(0, extensions::exit_code))
+}
+
+/// Reports the container engine and the QEMU interpreters, for bug reports.
+/// This is skipped (and never fails) when they can't be found.
+fn print_engine_info() {
+    match docker::engine_version() {
+        Some((engine, version)) => println!("container engine: {} ({version})", engine.display()),
+        None => println!("container engine: not found"),
+    }
+
+    // checking any emulated architecture will do
+    let emulated = Target::new_built_in("armv7-unknown-linux-gnueabihf");
+    match interpreter::is_registered(&emulated) {
+        Ok(true) => println!("binfmt_misc: QEMU interpreters registered"),
+        Ok(false) => println!("binfmt_misc: QEMU interpreters not registered"),
+        Err(_) => println!("binfmt_misc: not supported"),
+    }
+}
+
+/// Returns the exit status of the spawned `cargo`, if any
+fn run(args: cli::Args, target_list: &TargetList) -> Result<Option<ExitStatus>> {
+    if args.all.iter().any(|a| a == "--version" || a == "-V") && args.subcommand.is_none() {
+        println!("cross {}", build_info::CROSS_VERSION);
+        print_engine_info();
+    }
+
+    let verbose = args.verbose;
+    let needs_interpreter = args.needs_interpreter();
+
+    if args.subcommand == Some(Subcommand::CheckConfig) {
+        check_config(&args, target_list)?;
+        return Ok(None);
+    }
+    if args.subcommand == Some(Subcommand::SetupInterpreters) {
+        setup_interpreters(&args, target_list)?;
+        return Ok(None);
+    }
+
+    // outside of a project, only the environment configures `cross`
+    let mut cargo = Config::new(None).cargo();
+    let mut fallback = None;
+    if !args.needs_project() && args.emit_mounts.is_none() {
+        return cargo::run(&cargo, &args.all, verbose).map(Some);
+    }
+
+    let version_meta =
+        rustc_version::version_meta().wrap_err("couldn't fetch the `rustc` version")?;
+    let cd = env::current_dir().wrap_err("couldn't get current directory")?;
+    if let Some(root) = project_root(&cd, &args)? {
+        let host = version_meta.host();
+        let manifest_path = manifest_path(&args)?;
+        let explicit_config_path = explicit_config_path(&args);
+        let profile = args.profile();
+        let config = Config::new(toml(&root, &args, manifest_path.as_deref())?);
+        cargo = config.cargo();
+        if !config.no_arch_warning() {
+            warn_if_translated(&host, verbose);
+        }
+        if args.subcommand == Some(Subcommand::Clean) {
+            docker::remove_servers(root.path(), verbose)?;
+        }
+        let target = match args.target.or_else(|| config.target(target_list)) {
+            Some(target) => target,
+            None if config.require_target()?
+                && args.subcommand.map_or(false, |sc| sc.needs_docker()) =>
+            {
+                return Err(eyre::eyre!(
+                    "no target was given, and `require-target` is set"
+                ))
+                .suggestion("pass `--target`, or set `build.default-target` in `Cross.toml`");
+            }
+            None => Target::from(host.triple(), target_list),
+        };
+        config.confusable_target(&target);
+        if let Some(spec) = target.shadowed_spec(&target_spec_dirs()) {
+            eprintln!(
+                "Warning: the target specification `{}` has the name of a built-in target, so the built-in \"{target}\" is used instead.",
+                spec.display()
+            );
+            eprintln!(" > Rename the file, or pass its path to `--target`, to use it.");
+        }
+        if args.subcommand.map_or(false, |sc| sc.needs_docker()) {
+            fallback = host
+                .fallback(&target)
+                .map(|f| (host.clone(), target.clone(), f));
+        }
+        if host.is_supported(Some(&target)) {
+            let mut sysroot = rustc::sysroot(&host, &target, verbose)?;
+            let channel = args.channel.or_else(|| config.toolchain(&target));
+            let (uses_xargo, toolchain) = if config.no_rustup()
+                || !rustc::is_rustup_toolchain(&sysroot)
+            {
+                if let Some(channel) = channel {
+                    eprintln!(
+                        "Warning: the toolchain at `{}` isn't managed by rustup, ignoring `{channel}`.",
+                        sysroot.display()
+                    );
+                }
+                // the target and `rust-src` are assumed to be provisioned
+                let uses_xargo = config
+                    .xargo(&target)?
+                    .unwrap_or_else(|| !target.is_builtin());
+                (uses_xargo, None)
+            } else {
+                let default_toolchain = sysroot
+                    .file_name()
+                    .and_then(|file_name| file_name.to_str())
+                    .ok_or_else(|| eyre::eyre!("couldn't get toolchain name"))?;
+                let toolchain = toolchain_name(default_toolchain, channel.as_deref());
+                sysroot.set_file_name(&toolchain);
+
+                let installed_toolchains = rustup::installed_toolchains(verbose)?;
+
+                if !installed_toolchains.into_iter().any(|t| t == toolchain) {
+                    rustup::install_toolchain(&toolchain, verbose)?;
+                    Event::ToolchainInstalled {
+                        toolchain: &toolchain,
+                    }
+                    .emit(args.json_messages);
+                }
+
+                let available_targets = rustup::available_targets(&toolchain, verbose)?;
+                let uses_xargo = config.xargo(&target)?.unwrap_or_else(|| {
+                    !target.is_builtin() || !available_targets.contains(&target)
+                });
+
+                if !uses_xargo
+                    && !available_targets.is_installed(&target)
+                    && available_targets.contains(&target)
+                {
+                    rustup::install(&target, &toolchain, verbose)?;
+                } else if !rustup::component_is_installed("rust-src", &toolchain, verbose)? {
+                    rustup::install_component("rust-src", &toolchain, verbose)?;
+                }
+
+                if args
+                    .subcommand
+                    .map(|sc| sc == Subcommand::Clippy)
+                    .unwrap_or(false)
+                    && !rustup::component_is_installed("clippy", &toolchain, verbose)?
+                {
+                    rustup::install_component("clippy", &toolchain, verbose)?;
+                }
+
+                (uses_xargo, Some(toolchain))
+            };
+
+            let image = match docker::image(&config, &target, &profile) {
+                Ok(image) => {
+                    Event::ImageResolved {
+                        target: target.triple(),
+                        image: &image,
+                    }
+                    .emit(args.json_messages);
+                    Some(image)
+                }
+                // a configured image that can't be used is a mistake to fix,
+                // rather than a reason to build on the host
+                Err(err) if config.image(&target).map_or(false, |image| image.is_some()) => {
+                    return Err(err);
+                }
+                Err(err) => {
+                    if args.subcommand.map_or(false, |sc| sc.needs_docker()) {
+                        let reason = HostFallback::NoImage(err.to_string());
+                        fallback = Some((host.clone(), target.clone(), reason));
+                    }
+                    None
+                }
+            };
+
+            let mut filtered_args = if args
+                .subcommand
+                .map_or(false, |s| !s.needs_target_in_command())
+            {
+                let mut filtered_args = Vec::new();
+                let mut args_iter = args.all.clone().into_iter();
+                while let Some(arg) = args_iter.next() {
+                    if arg == "--target" {
+                        args_iter.next();
+                    } else if arg.starts_with("--target=") {
+                        // NOOP
+                    } else {
+                        filtered_args.push(arg)
+                    }
+                }
+                filtered_args
+            // Make sure --target is present
+            } else if !args.all.iter().any(|a| a.starts_with("--target")) {
+                let mut args_with_target = args.all.clone();
+                args_with_target.push("--target".to_string());
+                args_with_target.push(target.triple().to_string());
+                args_with_target
+            } else {
+                args.all.clone()
+            };
+
+            if let Some(image) = image.filter(|_| {
+                target.needs_docker()
+                    && args.subcommand.map(|sc| sc.needs_docker()).unwrap_or(false)
+            }) {
+                docker::use_docker_context(verbose);
+
+                let emulated = interpreter::is_needed(&target, config.runner(&target)?.as_deref());
+                if args.subcommand == Some(Subcommand::Bench) && needs_interpreter && emulated {
+                    match bench_action(config.skip_emulated_bench(&target)?, config.bench_no_warn())
+                    {
+                        BenchAction::Skip => {
+                            eprintln!(
+                                "Skipping the benchmarks of {target}, which would run under \
+                                 emulation, as `bench = \"skip\"`."
+                            );
+                            return Ok(None);
+                        }
+                        BenchAction::Warn => eprintln!(
+                            "Warning: the benchmarks of {target} run under QEMU emulation, so \
+                             their timings don't reflect the performance on real hardware. \
+                             Set CROSS_BENCH_NO_WARN=1 to silence this."
+                        ),
+                        BenchAction::Run => {}
+                    }
+                }
+
+                if version_meta.needs_interpreter()
+                    && needs_interpreter
+                    && !config.assume_interpreters_registered()
+                    && emulated
+                {
+                    let qemu = config.qemu(&target);
+                    interpreter::ensure_registered(&target, qemu.as_deref(), verbose)?;
+                }
+
+                if args.locked {
+                    cargo::check_lockfile(&root, verbose)?;
+                }
+
+                let mounts = docker::mounts(
+                    &target,
+                    &args.target_dir,
+                    &root,
+                    &config,
+                    &sysroot,
+                    verbose,
+                    args.docker_in_docker,
+                )?;
+                if let Some(path) = &args.emit_mounts {
+                    mounts.write(path)?;
+                    return Ok(None);
+                }
+
+                if config.image(&target)?.is_some() && !config.no_image_check() {
+                    let toolchain_version = rustc::version(toolchain.as_deref(), verbose)?;
+                    if let Some(image_version) =
+                        docker::image_rustc_version(&image, &mounts, &toolchain_version, verbose)?
+                    {
+                        if let Some(warning) = docker::image_toolchain_mismatch(
+                            &image,
+                            &image_version,
+                            &toolchain_version,
+                        ) {
+                            eprintln!("Warning: {warning}");
+                        }
+                    }
+                }
+
+                let lock_path = lock_path(&config_path(
+                    root.path(),
+                    explicit_config_path.as_deref(),
+                    manifest_path.as_deref(),
+                ));
+                let mut lock = match &lock_path {
+                    Some(path) => CrossLock::read(path)?,
+                    None => CrossLock::default(),
+                };
+                let run_image = if args.locked {
+                    // the cached image is checked when the registry can't be
+                    // reached, or the engine can't ask it
+                    let current = match docker::remote_digest(&image, verbose)? {
+                        Some(digest) => Some(digest),
+                        None => docker::image_digest(&image, verbose)?,
+                    };
+                    lock.pinned(&target, &image, current.as_deref())?
+                } else {
+                    None
+                }
+                .unwrap_or_else(|| image.clone());
+
+                Event::ContainerStarted {
+                    target: target.triple(),
+                    image: &run_image,
+                }
+                .emit(args.json_messages);
+                // the executables built with `--no-run` are reported with
+                // their host paths, from the messages cargo writes to the target directory
+                let executables = (args.no_run
+                    && !args.all.iter().any(|a| a.starts_with("--message-format")))
+                .then(|| PathBuf::from("/target/.cross-test-executables.json"));
+                if executables.is_some() {
+                    let index = filtered_args
+                        .iter()
+                        .position(|a| a == "--")
+                        .unwrap_or(filtered_args.len());
+                    filtered_args.insert(index, "--message-format=json-render-diagnostics".into());
+                }
+
+                let mut env_vars = env_file::load(root.path(), config.env_file())?;
+                if args.subcommand == Some(Subcommand::Deb) {
+                    if let Some(arch) = target.debian_arch() {
+                        env_vars.push(("DEB_HOST_ARCH".to_string(), arch.to_string()));
+                    }
+                }
+                let started = Instant::now();
+                let status = docker::run(
+                    &target,
+                    &run_image,
+                    args.subcommand,
+                    &filtered_args,
+                    &mounts,
+                    &config,
+                    &env_vars,
+                    &args.engine_args,
+                    executables.as_deref(),
+                    args.server,
+                    args.keep_container_on_failure,
+                    uses_xargo,
+                    verbose,
+                )?;
+                if let Some(messages) = executables.and_then(|path| mounts.host_path(&path)) {
+                    if status.success() {
+                        let content = file::read(&messages)?;
+                        for executable in cargo::test_executables(&content) {
+                            let host_path = mounts.host_path(&executable).unwrap_or(executable);
+                            println!("{}", host_path.display());
+                        }
+                    }
+                    std::fs::remove_file(messages).ok();
+                }
+                Event::ContainerExited {
+                    target: target.triple(),
+                    code: status.code(),
+                }
+                .emit(args.json_messages);
+                Summary {
+                    target: target.triple(),
+                    image: &run_image,
+                    toolchain: toolchain.as_deref(),
+                    elapsed: started.elapsed(),
+                    code: status.code(),
+                }
+                .emit(args.timings || config.timings());
+
+                // a shell session doesn't build anything
+                let built = status.success() && args.subcommand != Some(Subcommand::Shell);
+                if built && config.build_info()? {
+                    if let Some(target_dir) = mounts.host_path(Path::new("/target")) {
+                        BuildInfo {
+                            target: target.triple(),
+                            toolchain: toolchain.as_deref(),
+                            image: &run_image,
+                            cross_version: build_info::CROSS_VERSION,
+                            source_commit: build_info::source_commit(root.path(), verbose),
+                        }
+                        .write(&target_dir)?;
+                    }
+                }
+
+                // like `Cargo.lock`, `Cross.lock` is left alone with `--locked`
+                if let Some(lock_path) = lock_path.filter(|_| built && !args.locked) {
+                    if let Some(digest) = docker::image_digest(&image, verbose)? {
+                        let locked = lock.get(&target);
+                        if locked.map_or(true, |l| l.image != image || l.digest != digest) {
+                            lock.insert(&target, &image, &digest);
+                            lock.write(&lock_path)?;
+                        }
+                    }
+                }
+                return Ok(Some(status));
+            }
+        }
+    }
+
+    if args.emit_mounts.is_some() {
+        eyre::bail!("`--emit-mounts` was given, but the build would not run in a container");
+    }
+    if args.subcommand == Some(Subcommand::Shell) {
+        eyre::bail!(
+            "`cross shell` opens a shell in the image of a target, so it needs a `--target` \
+             that is built in a container"
+        );
+    }
+    if let Some((host, target, fallback)) = fallback {
+        eprintln!("Warning: {}", fallback.message(&host, &target));
+    }
+
+    cargo::run(&cargo, &args.all, verbose).map(Some)
+}
+
+/// The directories `rustc` looks for target specifications in
+fn target_spec_dirs() -> Vec<PathBuf> {
+    env::current_dir()
+        .into_iter()
+        .chain(
+            env::var_os("RUST_TARGET_PATH")
+                .iter()
+                .flat_map(env::split_paths),
+        )
+        .collect()
+}
+
+/// Why `cargo` runs on the host instead of in a container
+#[derive(Debug, PartialEq)]
+enum HostFallback {
+    /// `CROSS_COMPATIBILITY_VERSION=0.2.1` doesn't build the target from this host
+    Unsupported,
+    /// the target is built without a container
+    NoContainer,
+    /// the target has no image, for the given reason
+    NoImage(String),
+}
+
+impl HostFallback {
+    /// The diagnostic printed before `cargo` runs on the host
+    fn message(&self, host: &Host, target: &Target) -> String {
+        let reason = match self {
+            HostFallback::Unsupported => format!(
+                "`CROSS_COMPATIBILITY_VERSION=0.2.1` limits `cross` to the host/target \
+                 combinations it supported in that version, which don't include {target} on a \
+                 {} host; unset it to build every target that has an image",
+                host.triple()
+            ),
+            HostFallback::NoContainer => format!(
+                "{target} isn't built in a container; set `CROSS_COMPATIBILITY_VERSION=0.2.1` \
+                 for the host-specific rules of `cross` 0.2.1"
+            ),
+            HostFallback::NoImage(reason) => reason.clone(),
+        };
+        format!("running `cargo` on the host for {target}, instead of in a container: {reason}")
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum BenchAction {
+    Run,
+    Warn,
+    Skip,
+}
+
+/// What to do with benchmarks that would run under emulation, where their
+/// timings are meaningless
+fn bench_action(skip: bool, no_warn: bool) -> BenchAction {
+    match (skip, no_warn) {
+        (true, _) => BenchAction::Skip,
+        (false, false) => BenchAction::Warn,
+        (false, true) => BenchAction::Run,
+    }
+}
+
+/// Warns, once, when `cross` runs translated by Rosetta, where the arm64
+/// container engine makes builds slow and sometimes fail
+fn warn_if_translated(host: &Host, verbose: bool) {
+    static WARNING: Once = Once::new();
+    if *host != Host::X86_64AppleDarwin {
+        return;
+    }
+
+    let arm64_hardware = Command::new("sysctl")
+        .args(["-n", "hw.optional.arm64"])
+        .run_and_get_stdout(verbose)
+        .ok();
+    if host.is_translated(arm64_hardware.as_deref()) {
+        WARNING.call_once(|| {
+            eprintln!(
+                "Warning: `cross` runs as x86_64 under Rosetta on an arm64 Mac, which makes builds slow \
+                 and can break them. Use a native (arm64) shell and an aarch64-apple-darwin toolchain, \
+                 or set CROSS_NO_ARCH_WARNING=1 to silence this."
+            );
+        });
+    }
+}
+
+/// `cross setup-interpreters`: registers the interpreters of the targets
+/// once, for the builds run with `CROSS_ASSUME_INTERPRETERS_REGISTERED=1`
+fn setup_interpreters(args: &cli::Args, target_list: &TargetList) -> Result<()> {
+    let cd = env::current_dir().wrap_err("couldn't get current directory")?;
+    let toml = match cargo::find_root(&cd) {
+        Some(root) => toml(&root, args, manifest_path(args)?.as_deref())?,
+        None => None,
+    };
+    let config = Config::new(toml);
+
+    for target in interpreter_targets(args, target_list) {
+        if !interpreter::is_needed(&target, config.runner(&target)?.as_deref()) {
+            println!("{target}: runs natively, no interpreter needed");
+            continue;
+        }
+
+        let before = interpreter::handlers();
+        let qemu = config.qemu(&target);
+        if interpreter::ensure_registered(&target, qemu.as_deref(), args.verbose)? {
+            let installed: Vec<_> = interpreter::handlers()
+                .difference(&before)
+                .cloned()
+                .collect();
+            if installed.is_empty() {
+                println!("{target}: registered the interpreters again");
+            } else {
+                println!("{target}: installed {}", installed.join(", "));
+            }
+        } else {
+            println!("{target}: already registered");
+        }
+    }
+
+    Ok(())
+}
+
+/// The targets of `cross setup-interpreters`, given as arguments or with
+/// `--target`, or else one emulated target, since the QEMU interpreters of
+/// all architectures are registered together
+fn interpreter_targets(args: &cli::Args, target_list: &TargetList) -> Vec<Target> {
+    let mut targets = args.targets.clone();
+    let mut rest = args
+        .all
+        .iter()
+        .skip_while(|arg| *arg != "setup-interpreters")
+        .skip(1);
+    while let Some(arg) = rest.next() {
+        if arg == "--target" {
+            rest.next();
+        } else if !arg.starts_with('-') {
+            targets.push(Target::from(arg, target_list));
+        }
+    }
+
+    if targets.is_empty() {
+        targets.push(Target::new_built_in("armv7-unknown-linux-gnueabihf"));
+    }
+    targets
+}
+
+/// `cross check-config`: reports the issues of the `Cross.toml` of the project
+/// without building, as JSON lines on stdout with `--message-format=json`
+fn check_config(args: &cli::Args, target_list: &TargetList) -> Result<()> {
+    let cd = env::current_dir().wrap_err("couldn't get current directory")?;
+    let root = project_root(&cd, args)?.map_or(cd, |root| root.path().to_path_buf());
+    let path = config_path(
+        &root,
+        explicit_config_path(args).as_deref(),
+        manifest_path(args)?.as_deref(),
+    );
+    if !path.exists() {
+        eyre::bail!("there is no `Cross.toml` at `{}`", path.display());
+    }
+
+    let content =
+        file::read(&path).wrap_err_with(|| format!("could not read file `{}`", path.display()))?;
+    let issues = config_issues(&content, target_list)
+        .wrap_err_with(|| format!("failed to parse file `{}` as TOML", path.display()))?;
+    for issue in &issues {
+        if args.json_messages {
+            println!("{}", serde_json::to_string(issue)?);
+        } else {
+            eprintln!("{}: {issue}", path.display());
+        }
+    }
+
+    if issues.is_empty() {
+        if !args.json_messages {
+            eprintln!("`{}` is valid", path.display());
+        }
+        Ok(())
+    } else {
+        Err(eyre::eyre!(
+            "found {} issue(s) in `{}`",
+            issues.len(),
+            path.display()
+        ))
+    }
+}
+
+/// The issues of `Cross.toml`, including the values that are only checked
+/// once they're used by a build
+fn config_issues(content: &str, target_list: &TargetList) -> Result<Vec<Issue>> {
+    let (toml, mut issues) = CrossToml::check(content, target_list)?;
+    let mut targets: Vec<_> = toml.targets.keys().cloned().collect();
+    targets.sort_by(|a, b| a.triple().cmp(b.triple()));
+    let config = Config::new(Some(toml));
+
+    let mut resolve = |key: String, result: Result<()>| {
+        if let Err(err) = result {
+            issues.push(Issue::new(content, key, format!("{err:#}")));
+        }
+    };
+    resolve(
+        "build.max-image-age".into(),
+        config.max_image_age().map(drop),
+    );
+    resolve("build.cargo-home".into(), config.cargo_home().map(drop));
+    resolve("build.registry".into(), config.registry().map(drop));
+    resolve("build.source".into(), config.source().map(drop));
+    resolve("build.hostname".into(), config.hostname().map(drop));
+    for target in &targets {
+        let key = |name: &str| format!("target.{target}.{name}");
+        resolve(key("android-api"), config.android_api(target).map(drop));
+        resolve(key("memory"), config.memory(target).map(drop));
+        resolve(key("cpus"), config.cpus(target).map(drop));
+        resolve(key("bench"), config.skip_emulated_bench(target).map(drop));
+    }
+
+    Ok(issues)
+}
+
+/// The root of the Cargo project in `dir`. It's an error to build outside of
+/// one, unless the manifest is given with `--manifest-path`.
+fn project_root(dir: &Path, args: &cli::Args) -> Result<Option<Root>> {
+    match cargo::find_root(dir) {
+        None if args.manifest_path.is_none() => Err(eyre::eyre!(
+            "not inside a Cargo project: no `Cargo.toml` in `{}` or its parents",
+            dir.display()
+        ))
+        .suggestion("run `cross` in the directory of a Cargo project, or pass `--manifest-path`"),
+        root => Ok(root),
+    }
+}
+
+/// The name of the toolchain for `channel`, on the host of `default_toolchain`
+fn toolchain_name(default_toolchain: &str, channel: Option<&str>) -> String {
+    if let Some(channel) = channel {
+        [channel]
+            .iter()
+            .copied()
+            .chain(default_toolchain.splitn(2, '-').skip(1))
+            .collect::<Vec<_>>()
+            .join("-")
+    } else {
+        default_toolchain.to_string()
+    }
+}
+
+/// Replaces `--target all` by the targets of `build.targets`
+fn expand_all_targets(args: cli::Args, target_list: &TargetList) -> Result<cli::Args> {
+    if !args.all_targets {
+        return Ok(args);
+    }
+
+    let targets = project_config(&args)?
+        .map(|config| config.targets(target_list))
+        .unwrap_or_default();
+    if targets.is_empty() {
+        return Err(eyre::eyre!(
+            "`--target all` was given, but no targets are configured"
+        ))
+        .suggestion("list them in `build.targets` of `Cross.toml`, or in `CROSS_BUILD_TARGETS`");
+    }
+
+    Ok(args.with_targets(targets))
+}
+
+/// Takes the targets of cargo's `build.target`, from `CARGO_BUILD_TARGET` or
+/// the `.cargo/config.toml` of `cd`, when none is given on the command line.
+/// They come before the `default-target` of `Cross.toml`.
+fn cargo_config_targets(args: cli::Args, target_list: &TargetList, cd: &Path) -> Result<cli::Args> {
+    if !args.targets.is_empty() || !args.subcommand.map_or(false, |sc| sc.needs_docker()) {
+        return Ok(args);
+    }
+
+    let targets = match env::var("CARGO_BUILD_TARGET") {
+        Ok(target) if !target.is_empty() => vec![target],
+        _ => cargo::config_targets(cd, home::cargo_home().ok().as_deref())?,
+    };
+    if targets.is_empty() {
+        return Ok(args);
+    }
+    Ok(args.with_targets(
+        targets
+            .iter()
+            .map(|target| Target::from(target, target_list))
+            .collect(),
+    ))
+}
+
+/// The configuration of the Cargo project in the current directory, if any
+fn project_config(args: &cli::Args) -> Result<Option<Config>> {
+    match cargo::root()? {
+        Some(root) => {
+            let manifest_path = manifest_path(args)?;
+            let toml = toml(&root, args, manifest_path.as_deref())?;
+            Ok(Some(Config::new(toml)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// The manifest given with `--manifest-path`, relative to the current
+/// directory like cargo takes it
+fn manifest_path(args: &cli::Args) -> Result<Option<PathBuf>> {
+    match &args.manifest_path {
+        Some(path) => Ok(Some(
+            env::current_dir()
+                .wrap_err("couldn't get current directory")?
+                .join(path),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// The path of `Cross.toml` given with `--cross-config` or to a
+/// `CrossBuilder`, or else with the `CROSS_CONFIG` environment variable
+fn explicit_config_path(args: &cli::Args) -> Option<PathBuf> {
+    args.config_path
+        .clone()
+        .or_else(|| env::var_os("CROSS_CONFIG").map(PathBuf::from))
+}
+
+/// The path of `Cross.toml`: the `explicit` one, or next to the manifest
+/// given with `--manifest-path` or at the root of its workspace, or else the
+/// closest one from the root of the Cargo project up
+fn config_path(root: &Path, explicit: Option<&Path>, manifest_path: Option<&Path>) -> PathBuf {
+    if let Some(explicit) = explicit {
+        return explicit.to_path_buf();
+    }
+
+    manifest_path
+        .and_then(Path::parent)
+        .and_then(|dir| {
+            let workspace_root = cargo::workspace_root(dir);
+            iter::once(dir)
+                .chain(workspace_root)
+                .map(|dir| dir.join("Cross.toml"))
+                .find(|path| path.exists())
+        })
+        .or_else(|| nearest_config_path(root))
+        .unwrap_or_else(|| root.join("Cross.toml"))
+}
+
+/// The closest `Cross.toml` from `dir` up, like cargo finds its configuration.
+/// The search stops at the root of the repository, the directory with `.git`,
+/// so the configuration of an unrelated project is never picked up.
+fn nearest_config_path(dir: &Path) -> Option<PathBuf> {
+    for dir in dir.ancestors() {
+        let path = dir.join("Cross.toml");
+        if path.exists() {
+            return Some(path);
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// The `Cross.toml` at `path` as it's spelled on disk: `path` itself, or a
+/// `cross.toml` next to it. The directory is listed rather than probed, since
+/// a case-insensitive filesystem finds `cross.toml` under either name.
+fn on_disk_config_path(path: &Path) -> Option<PathBuf> {
+    let dir = path.parent()?;
+    let name = path.file_name()?;
+    let mut lowercase = None;
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let entry_name = entry.file_name();
+        if entry_name == name {
+            return Some(path.to_path_buf());
+        } else if entry_name == "cross.toml" {
+            lowercase = Some(dir.join(entry_name));
+        }
+    }
+    lowercase
+}
+
+/// The `Cross.lock` next to the configuration at `config_path`. It's only
+/// written in projects with a configuration file, or a lockfile already.
+fn lock_path(config_path: &Path) -> Option<PathBuf> {
+    let lock_path = config_path.with_file_name("Cross.lock");
+    (on_disk_config_path(config_path).is_some() || lock_path.is_file()).then(|| lock_path)
+}
+
+/// Parses the `Cross.toml` found by `config_path`, if any
+fn toml(root: &Root, args: &cli::Args, manifest_path: Option<&Path>) -> Result<Option<CrossToml>> {
+    static LOWERCASE_WARNING: Once = Once::new();
+
+    let explicit = explicit_config_path(args);
+    let mut path = config_path(root.path(), explicit.as_deref(), manifest_path);
+
+    if explicit.is_none() {
+        match on_disk_config_path(&path) {
+            Some(found) if found != path => {
+                if !Config::new(None).config_allow_lowercase() {
+                    LOWERCASE_WARNING.call_once(|| {
+                        eprintln!("Warning: there's a file named cross.toml, instead of Cross.toml. You may want to rename it, or set CROSS_CONFIG_ALLOW_LOWERCASE=1 to use it; it won't be considered otherwise.");
+                    });
+                    return Ok(None);
+                }
+                LOWERCASE_WARNING.call_once(|| {
+                    eprintln!(
+                        "Warning: using `{}`. You may want to rename it to Cross.toml.",
+                        found.display()
+                    );
+                });
+                path = found;
+            }
+            Some(_) => {}
+            None => return Ok(None),
+        }
+    }
+
+    if path.exists() {
+        let content = file::read(&path)
+            .wrap_err_with(|| format!("could not read file `{}`", path.display()))?;
+
+        let (config, _) = CrossToml::parse(&content)
+            .wrap_err_with(|| format!("failed to parse file `{}` as TOML", path.display()))?;
+
+        Ok(Some(config))
+    } else {
+        Ok(None)
+    }
+}
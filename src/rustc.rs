@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use rustc_version::{Version, VersionMeta};
@@ -35,7 +35,7 @@ impl VersionMetaExt for VersionMeta {
 
 pub fn target_list(verbose: bool) -> Result<TargetList> {
     Command::new("rustc")
-        .args(&["--print", "target-list"])
+        .args(["--print", "target-list"])
         .run_and_get_stdout(verbose)
         .map(|s| TargetList {
             triples: s.lines().map(|l| l.to_owned()).collect(),
@@ -44,7 +44,7 @@ pub fn target_list(verbose: bool) -> Result<TargetList> {
 
 pub fn sysroot(host: &Host, target: &Target, verbose: bool) -> Result<PathBuf> {
     let mut stdout = Command::new("rustc")
-        .args(&["--print", "sysroot"])
+        .args(["--print", "sysroot"])
         .run_and_get_stdout(verbose)?;
 
     if stdout.ends_with('\n') {
@@ -58,3 +58,38 @@ pub fn sysroot(host: &Host, target: &Target, verbose: bool) -> Result<PathBuf> {
 
     Ok(PathBuf::from(stdout))
 }
+
+/// The version of `rustc`, for `toolchain` if given
+pub fn version(toolchain: Option<&str>, verbose: bool) -> Result<Version> {
+    let mut rustc = Command::new("rustc");
+    if let Some(toolchain) = toolchain {
+        rustc.arg(format!("+{toolchain}"));
+    }
+    rustc.print_verbose(verbose);
+    VersionMeta::for_command(rustc)
+        .map(|meta| meta.semver)
+        .wrap_err("couldn't fetch the `rustc` version")
+}
+
+/// Whether `sysroot` is a toolchain installed by rustup, in its `toolchains`
+/// directory, rather than a distribution's or a manually built one
+pub fn is_rustup_toolchain(sysroot: &Path) -> bool {
+    sysroot
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .map_or(false, |parent| parent == "toolchains")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rustup_toolchain() {
+        assert!(is_rustup_toolchain(Path::new(
+            "/home/user/.rustup/toolchains/stable-x86_64-unknown-linux-gnu"
+        )));
+        assert!(!is_rustup_toolchain(Path::new("/usr")));
+        assert!(!is_rustup_toolchain(Path::new("/opt/rust/build/stage2")));
+    }
+}
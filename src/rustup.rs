@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::process::Command;
 
 use crate::errors::*;
@@ -23,10 +25,40 @@ impl AvailableTargets {
     }
 }
 
+thread_local! {
+    /// The output of the `rustup` queries run so far, by their arguments. Each
+    /// spawns `rustup`, and they're repeated for every target that's built.
+    static QUERIES: RefCell<HashMap<Vec<String>, String>> = RefCell::new(HashMap::new());
+}
+
+/// Runs `rustup` with `args`, at most once until it's `forget`-ten
+fn query(args: &[&str], verbose: bool) -> Result<String> {
+    cached(args, || {
+        Command::new("rustup")
+            .args(args)
+            .run_and_get_stdout(verbose)
+    })
+}
+
+fn cached(args: &[&str], run: impl FnOnce() -> Result<String>) -> Result<String> {
+    let key: Vec<_> = args.iter().map(|a| a.to_string()).collect();
+    if let Some(out) = QUERIES.with(|queries| queries.borrow().get(&key).cloned()) {
+        return Ok(out);
+    }
+
+    let out = run()?;
+    QUERIES.with(|queries| queries.borrow_mut().insert(key, out.clone()));
+    Ok(out)
+}
+
+/// Drops the cached output of the query with `args`, after an install changed it
+fn forget(args: &[&str]) {
+    let key: Vec<_> = args.iter().map(|a| a.to_string()).collect();
+    QUERIES.with(|queries| queries.borrow_mut().remove(&key));
+}
+
 pub fn installed_toolchains(verbose: bool) -> Result<Vec<String>> {
-    let out = Command::new("rustup")
-        .args(&["toolchain", "list"])
-        .run_and_get_stdout(verbose)?;
+    let out = query(&["toolchain", "list"], verbose)?;
 
     Ok(out
         .lines()
@@ -40,9 +72,7 @@ pub fn installed_toolchains(verbose: bool) -> Result<Vec<String>> {
 }
 
 pub fn available_targets(toolchain: &str, verbose: bool) -> Result<AvailableTargets> {
-    let out = Command::new("rustup")
-        .args(&["target", "list", "--toolchain", toolchain])
-        .run_and_get_stdout(verbose)?;
+    let out = query(&["target", "list", "--toolchain", toolchain], verbose)?;
 
     let mut default = String::new();
     let mut installed = vec![];
@@ -68,8 +98,9 @@ pub fn available_targets(toolchain: &str, verbose: bool) -> Result<AvailableTarg
 }
 
 pub fn install_toolchain(toolchain: &str, verbose: bool) -> Result<()> {
+    forget(&["toolchain", "list"]);
     Command::new("rustup")
-        .args(&["toolchain", "add", toolchain, "--profile", "minimal"])
+        .args(["toolchain", "add", toolchain, "--profile", "minimal"])
         .run(verbose)
         .wrap_err_with(|| format!("couldn't install toolchain `{toolchain}`"))
 }
@@ -77,23 +108,51 @@ pub fn install_toolchain(toolchain: &str, verbose: bool) -> Result<()> {
 pub fn install(target: &Target, toolchain: &str, verbose: bool) -> Result<()> {
     let target = target.triple();
 
+    forget(&["target", "list", "--toolchain", toolchain]);
     Command::new("rustup")
-        .args(&["target", "add", target, "--toolchain", toolchain])
+        .args(["target", "add", target, "--toolchain", toolchain])
         .run(verbose)
         .wrap_err_with(|| format!("couldn't install `std` for {target}"))
 }
 
 pub fn install_component(component: &str, toolchain: &str, verbose: bool) -> Result<()> {
+    forget(&["component", "list", "--toolchain", toolchain]);
     Command::new("rustup")
-        .args(&["component", "add", component, "--toolchain", toolchain])
+        .args(["component", "add", component, "--toolchain", toolchain])
         .run(verbose)
         .wrap_err_with(|| format!("couldn't install the `{component}` component"))
 }
 
 pub fn component_is_installed(component: &str, toolchain: &str, verbose: bool) -> Result<bool> {
-    Ok(Command::new("rustup")
-        .args(&["component", "list", "--toolchain", toolchain])
-        .run_and_get_stdout(verbose)?
-        .lines()
-        .any(|l| l.starts_with(component) && l.contains("installed")))
+    Ok(
+        query(&["component", "list", "--toolchain", toolchain], verbose)?
+            .lines()
+            .any(|l| l.starts_with(component) && l.contains("installed")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn query_runs_once() -> Result<()> {
+        let spawned = Cell::new(0);
+        let rustup = || {
+            spawned.set(spawned.get() + 1);
+            Ok("rust-src (installed)\n".to_string())
+        };
+        let args = ["component", "list", "--toolchain", "stable"];
+
+        cached(&args, rustup)?;
+        cached(&args, rustup)?;
+        assert_eq!(spawned.get(), 1);
+
+        forget(&args);
+        cached(&args, rustup)?;
+        assert_eq!(spawned.get(), 2);
+
+        Ok(())
+    }
 }
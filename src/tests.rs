@@ -1,3 +1,8 @@
+mod check_config;
+mod config_path;
+mod host;
+mod project;
+mod target;
 mod toml;
 
 use std::{
@@ -0,0 +1,40 @@
+use crate::config_issues;
+use crate::rustc::TargetList;
+
+#[test]
+fn malformed_config() {
+    let target_list = TargetList {
+        triples: vec!["aarch64-unknown-linux-gnu".to_string()],
+    };
+    let content = r#"
+[build]
+imagee = "my/image"
+env-file-overrides = true
+
+[target.aarch64-unknown-linux-gnu]
+memory = "lots"
+
+[target.aarch64-unknown-linux-gnux]
+image = "my/image"
+"#;
+
+    let issues = config_issues(content, &target_list).unwrap();
+    let found: Vec<_> = issues
+        .iter()
+        .map(|issue| (issue.key.as_str(), issue.line))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("build.imagee", Some(3)),
+            ("target.aarch64-unknown-linux-gnux", Some(9)),
+            ("target.aarch64-unknown-linux-gnu.memory", Some(7)),
+        ],
+        "{issues:#?}"
+    );
+    assert_eq!(issues[0].message, "unknown key");
+
+    assert!(config_issues("[build]\nxargo = true\n", &target_list)
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::Path;
+
+use crate::cli::parse_args;
+use crate::rustc::TargetList;
+use crate::{config_path, explicit_config_path, lock_path, on_disk_config_path};
+
+#[test]
+fn nested_manifest_path() {
+    let dir = std::env::temp_dir().join(format!("cross-config-path-{}", std::process::id()));
+    let workspace = dir.join("workspace");
+    let member = workspace.join("crates").join("member");
+    let elsewhere = dir.join("elsewhere");
+    fs::create_dir_all(&member).unwrap();
+    fs::create_dir_all(&elsewhere).unwrap();
+    fs::write(
+        workspace.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"crates/member\"]\n",
+    )
+    .unwrap();
+    fs::write(member.join("Cargo.toml"), "[package]\nname = \"member\"\n").unwrap();
+    fs::write(workspace.join("Cross.toml"), "").unwrap();
+
+    let manifest = member.join("Cargo.toml");
+    // the workspace root's, when there's none next to the manifest
+    assert_eq!(
+        config_path(&elsewhere, None, Some(&manifest)),
+        workspace.join("Cross.toml")
+    );
+
+    fs::write(member.join("Cross.toml"), "").unwrap();
+    assert_eq!(
+        config_path(&elsewhere, None, Some(&manifest)),
+        member.join("Cross.toml")
+    );
+
+    assert_eq!(
+        config_path(&elsewhere, None, None),
+        elsewhere.join("Cross.toml")
+    );
+    assert_eq!(
+        config_path(&elsewhere, None, Some(Path::new("/nonexistent/Cargo.toml"))),
+        elsewhere.join("Cross.toml")
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn parent_directories() {
+    let dir = std::env::temp_dir().join(format!("cross-config-parent-{}", std::process::id()));
+    let repo = dir.join("repo");
+    let workspace = repo.join("crates").join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+    fs::write(workspace.join("Cargo.toml"), "[workspace]\n").unwrap();
+
+    // the closest one above the workspace
+    fs::write(repo.join("crates").join("Cross.toml"), "").unwrap();
+    fs::write(repo.join("Cross.toml"), "").unwrap();
+    assert_eq!(
+        config_path(&workspace, None, None),
+        repo.join("crates").join("Cross.toml")
+    );
+
+    // but not above the root of the repository
+    fs::remove_file(repo.join("crates").join("Cross.toml")).unwrap();
+    fs::remove_file(repo.join("Cross.toml")).unwrap();
+    fs::create_dir_all(repo.join(".git")).unwrap();
+    fs::write(dir.join("Cross.toml"), "").unwrap();
+    assert_eq!(
+        config_path(&workspace, None, None),
+        workspace.join("Cross.toml")
+    );
+
+    // and `CROSS_CONFIG` still wins
+    let explicit = dir.join("Cross.toml");
+    assert_eq!(config_path(&workspace, Some(&explicit), None), explicit);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn lowercase_config() {
+    let dir = std::env::temp_dir().join(format!("cross-config-case-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("Cross.toml");
+    assert_eq!(on_disk_config_path(&path), None);
+
+    fs::write(dir.join("cross.toml"), "").unwrap();
+    assert_eq!(on_disk_config_path(&path), Some(dir.join("cross.toml")));
+
+    fs::remove_file(dir.join("cross.toml")).unwrap();
+    fs::write(&path, "").unwrap();
+    assert_eq!(on_disk_config_path(&path), Some(path.clone()));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cross_config_flag() {
+    let target_list = TargetList { triples: vec![] };
+    let args = parse_args(
+        [
+            "build",
+            "--cross-config",
+            "ci/Cross.toml",
+            "--config",
+            "build.jobs=4",
+        ]
+        .iter()
+        .map(|a| a.to_string()),
+        &target_list,
+    );
+    assert_eq!(args.all, ["build", "--config", "build.jobs=4"]);
+
+    // relative to the current directory, and over `CROSS_CONFIG` and discovery
+    let expected = std::env::current_dir().unwrap().join("ci/Cross.toml");
+    let explicit = explicit_config_path(&args);
+    assert_eq!(explicit.as_deref(), Some(expected.as_path()));
+    assert_eq!(
+        config_path(
+            Path::new("/project"),
+            explicit.as_deref(),
+            Some(Path::new("/project/Cargo.toml"))
+        ),
+        expected
+    );
+}
+
+#[test]
+fn lock_next_to_config() {
+    let dir = std::env::temp_dir().join(format!("cross-lock-path-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("Cross.toml");
+
+    // no lockfile is written into projects without a configuration
+    assert_eq!(lock_path(&config), None);
+
+    fs::write(dir.join("Cross.lock"), "").unwrap();
+    assert_eq!(lock_path(&config), Some(dir.join("Cross.lock")));
+    fs::remove_file(dir.join("Cross.lock")).unwrap();
+
+    fs::write(&config, "").unwrap();
+    assert_eq!(lock_path(&config), Some(dir.join("Cross.lock")));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
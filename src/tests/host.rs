@@ -0,0 +1,69 @@
+use crate::{docker, Config, Host, HostFallback, Target, TargetList};
+
+#[test]
+fn aarch64_windows_host() {
+    let host = Host::from("aarch64-pc-windows-msvc");
+    assert_eq!(host, Host::Aarch64PcWindowsMsvc);
+    assert_eq!(host.triple(), "aarch64-pc-windows-msvc");
+
+    let target = Target::new_built_in("aarch64-unknown-linux-gnu");
+    assert!(host.is_supported(Some(&target)));
+    assert!(!host.is_supported(None));
+}
+
+#[test]
+fn freebsd_host() {
+    let host = Host::from("x86_64-unknown-freebsd");
+    assert_eq!(host, Host::X86_64UnknownFreebsd);
+    assert_eq!(host.triple(), "x86_64-unknown-freebsd");
+    let native: Target = host.clone().into();
+    assert_eq!(native, Target::new_built_in("x86_64-unknown-freebsd"));
+
+    let target = Target::new_built_in("aarch64-unknown-linux-gnu");
+    assert!(host.is_supported(Some(&target)));
+}
+
+#[test]
+fn host_fallback_reasons() {
+    let host = Host::from("x86_64-unknown-linux-gnu");
+    let target = Target::new_built_in("wasm32-unknown-unknown");
+    assert!(!host.is_supported(Some(&target)));
+    let fallback = host.fallback(&target).unwrap();
+    assert_eq!(fallback, HostFallback::NoContainer);
+    let message = fallback.message(&host, &target);
+    assert!(message.contains("running `cargo` on the host for wasm32-unknown-unknown"));
+    assert!(message.contains("isn't built in a container"));
+
+    let supported = Target::new_built_in("aarch64-unknown-linux-gnu");
+    assert_eq!(host.fallback(&supported), None);
+    let native = Target::new_built_in("x86_64-unknown-linux-gnu");
+    assert_eq!(host.fallback(&native), None);
+
+    let message = HostFallback::Unsupported.message(&host, &supported);
+    assert!(message.contains("running `cargo` on the host for aarch64-unknown-linux-gnu"));
+    assert!(message.contains("`CROSS_COMPATIBILITY_VERSION=0.2.1` limits `cross`"));
+    assert!(message.contains("on a x86_64-unknown-linux-gnu host"));
+
+    let custom = Target::from(
+        "thumbv7em-custom-none-eabi",
+        &TargetList { triples: vec![] },
+    );
+    let err = docker::image(&Config::new(None), &custom, "debug").unwrap_err();
+    let message = HostFallback::NoImage(err.to_string()).message(&host, &custom);
+    assert!(message.contains("running `cargo` on the host for thumbv7em-custom-none-eabi"));
+    assert!(message.contains("does not provide a Docker image"));
+}
+
+#[test]
+fn rosetta_translation() {
+    let intel = Host::from("x86_64-apple-darwin");
+    assert!(intel.is_translated(Some("1\n")));
+    assert!(!intel.is_translated(Some("0\n")));
+    // the key doesn't exist on Intel Macs
+    assert!(!intel.is_translated(None));
+
+    let native = Host::from("aarch64-apple-darwin");
+    assert!(!native.is_translated(Some("1\n")));
+    let linux = Host::from("x86_64-unknown-linux-gnu");
+    assert!(!linux.is_translated(Some("1")));
+}
@@ -0,0 +1,25 @@
+use std::fs;
+
+use crate::cli::parse_args;
+use crate::project_root;
+use crate::rustc::TargetList;
+
+#[test]
+fn outside_of_a_project() {
+    let dir = std::env::temp_dir().join(format!("cross-no-project-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let target_list = TargetList { triples: vec![] };
+
+    let build = parse_args(["build".to_string()], &target_list);
+    let err = project_root(&dir, &build).unwrap_err();
+    assert!(
+        err.to_string().starts_with("not inside a Cargo project"),
+        "{err}"
+    );
+
+    fs::write(dir.join("Cargo.toml"), "[package]\nname = \"project\"\n").unwrap();
+    let root = project_root(&dir.join("src"), &build).unwrap().unwrap();
+    assert_eq!(root.path(), dir);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
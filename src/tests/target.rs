@@ -0,0 +1,160 @@
+use std::fs;
+
+use crate::cli::parse_args;
+use crate::cross_toml::CrossToml;
+use crate::rustc::TargetList;
+use crate::{
+    bench_action, cargo_config_targets, interpreter, interpreter_targets, BenchAction, Config,
+    Target,
+};
+
+#[test]
+fn debian_arch() {
+    for (triple, arch) in [
+        ("x86_64-unknown-linux-gnu", "amd64"),
+        ("i686-unknown-linux-gnu", "i386"),
+        ("aarch64-unknown-linux-gnu", "arm64"),
+        ("aarch64-unknown-linux-musl", "arm64"),
+        ("armv7-unknown-linux-gnueabihf", "armhf"),
+        ("arm-unknown-linux-gnueabi", "armel"),
+        ("mips64el-unknown-linux-gnuabi64", "mips64el"),
+        ("powerpc64le-unknown-linux-gnu", "ppc64el"),
+        ("riscv64gc-unknown-linux-gnu", "riscv64"),
+    ] {
+        assert_eq!(
+            Target::new_built_in(triple).debian_arch(),
+            Some(arch),
+            "{triple}"
+        );
+    }
+
+    assert_eq!(
+        Target::new_built_in("x86_64-pc-windows-gnu").debian_arch(),
+        None
+    );
+    assert_eq!(
+        Target::new_built_in("aarch64-linux-android").debian_arch(),
+        None
+    );
+}
+
+#[test]
+fn shadowed_spec() {
+    let dir = std::env::temp_dir().join(format!("cross-target-spec-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let spec = dir.join("aarch64-unknown-linux-gnu.json");
+    fs::write(&spec, "{}").unwrap();
+    fs::write(dir.join("thumbv7em-custom.json"), "{}").unwrap();
+    let dirs = [dir.join("missing"), dir.clone()];
+
+    assert_eq!(
+        Target::new_built_in("aarch64-unknown-linux-gnu").shadowed_spec(&dirs),
+        Some(spec)
+    );
+    assert_eq!(
+        Target::new_built_in("armv7-unknown-linux-gnueabihf").shadowed_spec(&dirs),
+        None
+    );
+    assert_eq!(
+        Target::new_custom("thumbv7em-custom").shadowed_spec(&dirs),
+        None
+    );
+
+    fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn setup_interpreters_targets() {
+    let target_list = TargetList {
+        triples: vec![
+            "aarch64-unknown-linux-gnu".to_string(),
+            "x86_64-pc-windows-gnu".to_string(),
+        ],
+    };
+    let targets = |args: &[&str]| {
+        let args = parse_args(args.iter().map(|a| a.to_string()), &target_list);
+        interpreter_targets(&args, &target_list)
+    };
+
+    assert_eq!(
+        targets(&["setup-interpreters", "x86_64-pc-windows-gnu"]),
+        [Target::new_built_in("x86_64-pc-windows-gnu")]
+    );
+    assert_eq!(
+        targets(&[
+            "setup-interpreters",
+            "--target",
+            "aarch64-unknown-linux-gnu"
+        ]),
+        [Target::new_built_in("aarch64-unknown-linux-gnu")]
+    );
+    assert_eq!(
+        targets(&["setup-interpreters"]),
+        [Target::new_built_in("armv7-unknown-linux-gnueabihf")]
+    );
+}
+
+#[test]
+fn emulated_benches() {
+    let toml = r#"
+        [target.aarch64-unknown-linux-gnu]
+        bench = "skip"
+
+        [target.riscv64gc-unknown-linux-gnu]
+        bench = "sometimes"
+    "#;
+    let config = Config::new(Some(CrossToml::parse(toml).unwrap().0));
+    let skipped = Target::new_built_in("aarch64-unknown-linux-gnu");
+    let warned = Target::new_built_in("armv7-unknown-linux-gnueabihf");
+    assert!(interpreter::is_needed(&skipped, None));
+    assert!(interpreter::is_needed(&warned, None));
+
+    let skip = config.skip_emulated_bench(&skipped).unwrap();
+    assert_eq!(bench_action(skip, false), BenchAction::Skip);
+    assert_eq!(bench_action(skip, true), BenchAction::Skip);
+
+    let skip = config.skip_emulated_bench(&warned).unwrap();
+    assert_eq!(bench_action(skip, false), BenchAction::Warn);
+    assert_eq!(bench_action(skip, true), BenchAction::Run);
+
+    let invalid = Target::new_built_in("riscv64gc-unknown-linux-gnu");
+    assert!(config.skip_emulated_bench(&invalid).is_err());
+}
+
+#[test]
+fn target_from_cargo_config() {
+    let dir = std::env::temp_dir().join(format!("cross-cargo-target-{}", std::process::id()));
+    fs::create_dir_all(dir.join(".cargo")).unwrap();
+    fs::write(
+        dir.join(".cargo/config.toml"),
+        "[build]\ntarget = \"aarch64-unknown-linux-gnu\"\n",
+    )
+    .unwrap();
+    let target_list = TargetList {
+        triples: vec!["aarch64-unknown-linux-gnu".to_string()],
+    };
+
+    let build = parse_args(["build".to_string()], &target_list);
+    let build = cargo_config_targets(build, &target_list, &dir).unwrap();
+    assert_eq!(
+        build.target,
+        Some(Target::new_built_in("aarch64-unknown-linux-gnu"))
+    );
+    assert_eq!(
+        build.all,
+        ["build", "--target", "aarch64-unknown-linux-gnu"]
+    );
+
+    // the command line wins
+    let explicit = parse_args(
+        ["build", "--target", "x86_64-unknown-linux-gnu"].map(String::from),
+        &target_list,
+    );
+    let explicit = cargo_config_targets(explicit, &target_list, &dir).unwrap();
+    assert_eq!(
+        explicit.all,
+        ["build", "--target", "x86_64-unknown-linux-gnu"]
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}